@@ -0,0 +1,34 @@
+use std::{env, error::Error, fs::OpenOptions, io, sync::Mutex};
+
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, EnvFilter};
+
+const DEFAULT_FILTER: &str = "info";
+
+/// Installs the global subscriber, configured by:
+///
+/// - `TRIRK_LOG`: filter directives such as `debug` or
+///   `info,connection=trace`, defaults to `info`
+/// - `TRIRK_LOG_FORMAT`: `json` for one JSON object per event, text otherwise
+/// - `TRIRK_LOG_FILE`: file the logs are appended to instead of stderr
+pub fn init() -> Result<(), Box<dyn Error>> {
+    let filter = match env::var("TRIRK_LOG") {
+        Ok(directives) => EnvFilter::try_new(directives)?,
+        Err(_) => EnvFilter::new(DEFAULT_FILTER),
+    };
+    let (writer, ansi) = match env::var("TRIRK_LOG_FILE") {
+        Ok(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            (BoxMakeWriter::new(Mutex::new(file)), false)
+        }
+        Err(_) => (BoxMakeWriter::new(io::stderr), true),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(ansi);
+    let installed = match env::var("TRIRK_LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().try_init(),
+        _ => builder.try_init(),
+    };
+    installed.map_err(|err| err as Box<dyn Error>)
+}
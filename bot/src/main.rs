@@ -1,95 +1,140 @@
-use std::{env, error::Error};
+mod logging;
+
+use std::{env, error::Error, time::Duration};
 
 use connection::{
-    twitch::CommandType,
-    twitch_irc::{config::TwitchConfig, TwitchIrc},
+    error::{TrirkError, TrirkErrorKind},
+    twitch::{ChannelName, CommandType, Event, TwitchMessage, UserNoticeKind},
+    twitch_irc::{capture::ReplayConnection, config::TwitchConfig, split::Sender, TwitchIrc},
 };
 use dotenv::dotenv;
+use futures::{stream::BoxStream, StreamExt};
+use tracing::{debug, error, info, warn};
+
+const MAX_LAG: Duration = Duration::from_secs(5);
+
+type Messages = BoxStream<'static, Result<TwitchMessage, TrirkError>>;
 
-const IRINEU: &str = "irineu, você não sabe nem eu";
+/// Replays the capture at `TRIRK_REPLAY` when set, without sending anything.
+async fn connect() -> Result<(Messages, Option<Sender>), Box<dyn Error>> {
+    if let Ok(path) = env::var("TRIRK_REPLAY") {
+        let speed = match env::var("TRIRK_REPLAY_SPEED") {
+            Ok(speed) => speed
+                .parse()
+                .map_err(|err| format!("TRIRK_REPLAY_SPEED: {err}"))?,
+            Err(_) => 1.0,
+        };
+        let replay = ReplayConnection::open(path).await?.with_speed(speed)?;
+        return Ok((replay.boxed(), None));
+    }
+    let configuration = TwitchConfig::from_env()?;
+    let irc = TwitchIrc::new(configuration)?;
+    let (reader, sender) = irc.open_connection().await?.split();
+    Ok((reader.boxed(), Some(sender)))
+}
+
+async fn privmsg(sender: &mut Option<Sender>, channel: &ChannelName, message: &str) {
+    let Some(sender) = sender else {
+        info!(%channel, text = message, "replay, not sending");
+        return;
+    };
+    let _ = sender
+        .privmsg_to(channel, message)
+        .await
+        .map_err(|err| error!(%channel, error = %err, "could not send message"));
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     dotenv().ok();
-    let channel = env::var("TRIRK_CHANNEL").expect("please set twitch channel env var");
-    let oauth = env::var("TRIRK_OAUTH").expect("please set twitch oauth env var");
-    let nickname = env::var("TRIRK_NICKNAME").expect("please set twitch nickname env var");
-    let configuration = TwitchConfig::new(nickname, oauth, channel);
-    let irc = TwitchIrc::new(configuration);
-    loop {
-        let mut irc_connection = irc.clone().open_connection().await?;
-        //TODO: handle user notice with extra tag msg-id: resub, maybe msg-id sub
-        'message: loop {
-            match irc_connection.read_next() {
-                Ok(msg) => match msg.command().command() {
-                    CommandType::UserNotice if msg.tags().is_some() => {
-                        let tags = msg.tags().as_ref().unwrap();
-                        let extra_tags = tags.extra_tags();
-                        if let Some(sub) = extra_tags.get("msg-id") {
-                            if sub.contains("sub") {
-                                let _ = irc_connection.privmsg(&format!(
-                                    "{nickname} fez a boa PogChamp",
-                                    nickname = tags.display_name()
-                                ));
-                            }
-                        }
-                    }
-                    CommandType::ClearChat => {
-                 
-                        if let Some(tags) = msg.tags() {
-                            if *tags.ban_duration() > 0 {
-                                let _ = irc_connection
-                                    .privmsg(&format!(
-                                        "{nickname} foi de base por {duration}s",
-                                        nickname = msg.parameters().as_ref().map_or(IRINEU.into(), |p| p.replace('\n', "")),
-                                        duration = tags.ban_duration()
-                                    ))
-                                    .map_err(|err| {
-                                        eprintln!("ERROR: could not send message privmsg: {err}")
-                                    });
-                            }
-                        }
-                    }
-                    CommandType::Join => println!(
-                        "{} entrou na brincadeira",
-                        msg.source().as_ref().map_or(IRINEU.into(), |s| s.nick())
-                    ),
-                    CommandType::Part(_) => println!(
-                        "{} saiu da brincadeira",
-                        msg.source().as_ref().map_or(IRINEU.into(), |s| s.nick())
-                    ),
-                    CommandType::PrivMSG => print!(
-                        "{}: {}",
-                        msg.source().as_ref().map_or(IRINEU.into(), |s| s.nick()),
-                        msg.parameters().as_ref().map_or("", |p| p)
-                    ),
+    logging::init()?;
+    let (mut messages, mut sender) = connect().await?;
+    while let Some(next) = messages.next().await {
+        if let Some(lag) = next.as_ref().ok().and_then(|msg| msg.lag()) {
+            if lag > MAX_LAG {
+                warn!(?lag, "falling behind chat");
+            }
+        }
+        match next {
+            Ok(msg) => match msg.into_event() {
+                Event::UserNotice {
+                    kind:
+                        UserNoticeKind::Sub
+                        | UserNoticeKind::Resub
+                        | UserNoticeKind::SubGift
+                        | UserNoticeKind::SubMysteryGift,
+                    channel,
+                    tags: Some(tags),
+                    ..
+                } if !tags.is_from_shared_chat() => {
+                    privmsg(
+                        &mut sender,
+                        &channel,
+                        &format!(
+                            "{nickname} fez a boa PogChamp",
+                            nickname = tags.display_name()
+                        ),
+                    )
+                    .await;
+                }
+                Event::Timeout {
+                    channel,
+                    user,
+                    duration,
+                    tags,
+                } if !tags.is_from_shared_chat() => {
+                    privmsg(
+                        &mut sender,
+                        &channel,
+                        &format!(
+                            "{user} foi de base por {duration}s",
+                            duration = duration.as_secs()
+                        ),
+                    )
+                    .await;
+                }
+                Event::Join { channel, user } => info!(%channel, %user, "entrou na brincadeira"),
+                Event::Part { channel, user } => info!(%channel, %user, "saiu da brincadeira"),
+                Event::ChatMessage {
+                    channel,
+                    sender,
+                    text,
+                    ..
+                } => {
+                    info!(%channel, user = %sender.nick(), "{text}")
+                }
+                Event::UserNotice { channel, kind, .. } => {
+                    info!(%channel, ?kind, "user notice")
+                }
+                Event::Other(msg) => match msg.command().command() {
                     CommandType::Numeric(n) => match *n {
                         1 => {
-                            println!("bot run");
+                            info!("bot run");
                             //let _ = irc_connection.privmsg("testando envio de msg do meu botzin em Rust ao entrar em um canal").await;
                         }
-                        3 => println!("server bem novinho uhuu"),
-                        _ => eprintln!("unknown numeric command '{}': {msg:?}", n),
+                        3 => info!("server bem novinho uhuu"),
+                        _ => debug!(numeric = n, ?msg, "unknown numeric command"),
                     },
-                    CommandType::Ping => {
-                        let _ = irc_connection
-                            .pong()
-                            .map_err(|err| eprintln!("ERROR: could not send pong message: {err}"));
-                    }
                     CommandType::UserState => {
-                        dbg!(msg);
+                        debug!(?msg, "user state");
                     }
-
-                    CommandType::UserNotice => println!("user notice: {msg:?}"),
-                    _ => println!("'{msg:?}'not implemented yet"),
+                    _ => debug!(?msg, "not implemented yet"),
                 },
-                Err(e) => {
-                    eprintln!("{e}");
-                    if &e.to_string()[..] == "Parse - empty irc message" {
-                        break 'message;
-                    }
-                }
+                event => debug!(?event, "not implemented yet"),
+            },
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    TrirkErrorKind::ConnectionClosed | TrirkErrorKind::Auth
+                ) =>
+            {
+                return Err(e.into())
+            }
+            Err(e) if matches!(e.kind(), TrirkErrorKind::RateLimited) => {
+                warn!(error = %e, "message dropped by twitch")
             }
+            Err(e) => error!(error = %e),
         }
     }
+    Ok(())
 }
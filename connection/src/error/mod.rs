@@ -1,11 +1,14 @@
-use std::{error::Error, fmt::Display, string::FromUtf8Error};
+use std::{error::Error, fmt::Display, io};
 
 use parser::trirk_parser::error::UnparsableError;
 
+type Source = Box<dyn Error + Send + Sync + 'static>;
+
 #[derive(Debug)]
 pub struct TrirkError {
     message: String,
     kind: TrirkErrorKind,
+    source: Option<Source>,
 }
 
 impl Display for TrirkError {
@@ -14,37 +17,135 @@ impl Display for TrirkError {
     }
 }
 
-#[derive(Debug)]
+impl TrirkError {
+    pub(crate) fn new<T: Into<String>>(kind: TrirkErrorKind, message: T) -> Self {
+        Self {
+            message: message.into(),
+            kind,
+            source: None,
+        }
+    }
+
+    /// Like [`TrirkError::new`], keeping the error that caused it for
+    /// [`Error::source`].
+    pub(crate) fn with_source<T: Into<String>>(
+        kind: TrirkErrorKind,
+        message: T,
+        source: impl Into<Source>,
+    ) -> Self {
+        Self {
+            message: message.into(),
+            kind,
+            source: Some(source.into()),
+        }
+    }
+
+    /// The same kind and message without the source, for reporting one
+    /// failure to several waiters.
+    pub(crate) fn duplicate(&self) -> Self {
+        Self::new(self.kind, self.message.clone())
+    }
+
+    /// A [`TrirkErrorKind::ConnectionClosed`] for a handle whose background
+    /// task is gone, `what` names the task ("connection", "pool").
+    pub(crate) fn closed(what: &str) -> Self {
+        Self::new(TrirkErrorKind::ConnectionClosed, format!("{what} closed"))
+    }
+
+    pub fn kind(&self) -> &TrirkErrorKind {
+        &self.kind
+    }
+
+    /// The parser's error behind a [`TrirkErrorKind::Parse`].
+    pub fn parse_error(&self) -> Option<&UnparsableError> {
+        self.source.as_ref()?.downcast_ref()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrirkErrorKind {
+    /// A line Twitch sent could not be parsed, see [`TrirkError::parse_error`].
     Parse,
     Io,
-    Utf8,
+    /// The connection was closed and will not be re-established, e.g. the
+    /// reconnect policy gave up or every handle was dropped.
+    ConnectionClosed,
+    Timeout,
+    JoinRejected,
+    Config,
+    /// Twitch refused the login, e.g. an expired or malformed oauth token.
+    Auth,
+    CapabilityRejected,
+    /// Twitch dropped a message because it was sent too quickly.
+    RateLimited,
+    /// The channel is not joined, e.g. sending through a
+    /// [`TwitchPool`](crate::twitch_irc::pool::TwitchPool) to one it never
+    /// joined.
+    NotJoined,
 }
 
 impl From<UnparsableError> for TrirkError {
     fn from(value: UnparsableError) -> Self {
-        Self {
-            message: value.to_string(),
-            kind: TrirkErrorKind::Parse,
-        }
+        Self::with_source(TrirkErrorKind::Parse, value.to_string(), value)
     }
 }
 
-impl From<std::io::Error> for TrirkError {
-    fn from(value: std::io::Error) -> Self {
-        Self {
-            message: value.to_string(),
-            kind: TrirkErrorKind::Io,
-        }
+impl From<io::Error> for TrirkError {
+    fn from(value: io::Error) -> Self {
+        let kind = match value.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected => TrirkErrorKind::ConnectionClosed,
+            io::ErrorKind::TimedOut => TrirkErrorKind::Timeout,
+            _ => TrirkErrorKind::Io,
+        };
+        Self::with_source(kind, value.to_string(), value)
     }
 }
 
-impl From<FromUtf8Error> for TrirkError {
-    fn from(value: FromUtf8Error) -> Self {
-        Self {
-            message: value.to_string(),
-            kind: TrirkErrorKind::Utf8,
-        }
+impl Error for TrirkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn Error + 'static))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{error::Error, io};
+
+    use parser::trirk_parser::error::{UnparsableError, UnparsableErrorKind};
+
+    use super::{TrirkError, TrirkErrorKind};
+
+    #[test]
+    fn should_keep_the_parser_error() {
+        let unparsable = || UnparsableError::new(UnparsableErrorKind::MissingCommand, "no command");
+        let err = TrirkError::from(unparsable());
+        assert!(matches!(err.kind(), TrirkErrorKind::Parse));
+        assert_eq!(Some(&unparsable()), err.parse_error());
+        let source = err.source().unwrap().downcast_ref::<UnparsableError>();
+        assert_eq!(
+            Some(UnparsableErrorKind::MissingCommand),
+            source.map(UnparsableError::kind)
+        );
+        assert_eq!("no command", err.source().unwrap().to_string());
+    }
+
+    #[test]
+    fn should_tell_closed_connections_from_other_io_errors() {
+        let closed = TrirkError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(matches!(closed.kind(), TrirkErrorKind::ConnectionClosed));
+        let denied = TrirkError::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(denied.kind(), TrirkErrorKind::Io));
+        let source = denied.source().unwrap().downcast_ref::<io::Error>();
+        assert_eq!(
+            Some(io::ErrorKind::PermissionDenied),
+            source.map(io::Error::kind)
+        );
+        assert!(denied.parse_error().is_none());
     }
 }
-impl Error for TrirkError {}
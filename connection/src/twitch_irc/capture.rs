@@ -0,0 +1,379 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use futures::{channel::mpsc, Stream, StreamExt};
+use parser::{
+    trirk_parser::{line_buffer::LineBuffer, TrirkParser},
+    Timestamp, TwitchMessage,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    time::{Instant, Sleep},
+};
+use tracing::warn;
+
+use crate::error::{TrirkError, TrirkErrorKind};
+
+use super::transport::IrcStream;
+
+/// When a replayed message too slow to schedule is due, like tokio's own
+/// far future.
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+/// Which way a captured line went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    fn marker(self) -> char {
+        match self {
+            Direction::Inbound => '<',
+            Direction::Outbound => '>',
+        }
+    }
+}
+
+/// One line of a capture file: `<unix millis> <'<' or '>'> <raw line>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub at: Timestamp,
+    pub direction: Direction,
+    pub line: String,
+}
+
+impl Record {
+    pub fn parse(entry: &str) -> Option<Self> {
+        let (at, rest) = entry.split_once(' ')?;
+        let (direction, line) = rest.split_once(' ')?;
+        let direction = match direction {
+            "<" => Direction::Inbound,
+            ">" => Direction::Outbound,
+            _ => return None,
+        };
+        Some(Self {
+            at: Timestamp::from_millis(at.parse().ok()?)?,
+            direction,
+            line: line.into(),
+        })
+    }
+}
+
+/// Appends the lines handed to it to a capture file from a background task.
+#[derive(Clone)]
+pub(super) struct Recorder {
+    entries: mpsc::UnboundedSender<String>,
+}
+
+impl Recorder {
+    pub(super) async fn create(path: &Path) -> io::Result<Self> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let (entries, mut receiver) = mpsc::unbounded::<String>();
+        tokio::spawn(async move {
+            while let Some(entry) = receiver.next().await {
+                if let Err(err) = file.write_all(entry.as_bytes()).await {
+                    warn!(error = %err, "could not write to the capture, recording stopped");
+                    return;
+                }
+            }
+        });
+        Ok(Self { entries })
+    }
+
+    fn record(&self, direction: Direction, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        // keep tokens out of capture files, they get shared in bug reports
+        let line = if line.starts_with("PASS ") {
+            "PASS ***".into()
+        } else {
+            line
+        };
+        let entry = format!(
+            "{} {} {line}\n",
+            Timestamp::now().as_millis(),
+            direction.marker()
+        );
+        let _ = self.entries.unbounded_send(entry);
+    }
+}
+
+/// Stream wrapper recording every complete line read or written.
+pub(super) struct Recording {
+    inner: Box<dyn IrcStream>,
+    recorder: Recorder,
+    inbound: LineBuffer,
+    outbound: LineBuffer,
+}
+
+impl Recording {
+    pub(super) fn new(inner: Box<dyn IrcStream>, recorder: Recorder) -> Self {
+        Self {
+            inner,
+            recorder,
+            inbound: LineBuffer::new(),
+            outbound: LineBuffer::new(),
+        }
+    }
+}
+
+fn record_lines(recorder: &Recorder, lines: &mut LineBuffer, direction: Direction, bytes: &[u8]) {
+    // oversized lines are left out of the capture, the driver reports them
+    let _ = lines.extend(bytes);
+    while let Some(line) = lines.next_line() {
+        recorder.record(direction, &line);
+    }
+}
+
+impl AsyncRead for Recording {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = &buf.filled()[before..];
+        record_lines(&this.recorder, &mut this.inbound, Direction::Inbound, read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Recording {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        record_lines(
+            &this.recorder,
+            &mut this.outbound,
+            Direction::Outbound,
+            &buf[..written],
+        );
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Plays the inbound lines of a capture file back as messages, keeping the
+/// original pacing (scaled by [`ReplayConnection::with_speed`]) so the bot
+/// can run offline against real traffic.
+pub struct ReplayConnection {
+    records: VecDeque<Record>,
+    parser: TrirkParser,
+    speed: f64,
+    /// When the replay started and the capture time it started at.
+    started: Option<(Instant, Timestamp)>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl ReplayConnection {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, TrirkError> {
+        let capture = tokio::fs::read_to_string(path).await?;
+        Self::from_capture(&capture)
+    }
+
+    pub fn from_capture(capture: &str) -> Result<Self, TrirkError> {
+        let records = capture
+            .lines()
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                Record::parse(entry).ok_or_else(|| {
+                    TrirkError::new(
+                        TrirkErrorKind::Parse,
+                        format!("invalid capture entry '{entry}'"),
+                    )
+                })
+            })
+            .filter(|record| {
+                record
+                    .as_ref()
+                    .map_or(true, |record| record.direction == Direction::Inbound)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            records,
+            parser: TrirkParser::new(),
+            speed: 1.0,
+            started: None,
+            sleep: None,
+        })
+    }
+
+    /// Replays `speed` times faster than recorded; `f64::INFINITY` replays
+    /// without waiting. Fails with a
+    /// [`TrirkErrorKind::Config`] error unless `speed` is above zero.
+    pub fn with_speed(mut self, speed: f64) -> Result<Self, TrirkError> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(TrirkError::new(
+                TrirkErrorKind::Config,
+                format!("replay speed must be above zero, got {speed}"),
+            ));
+        }
+        self.speed = speed;
+        Ok(self)
+    }
+
+    /// Replaces the default parser, e.g. with one that has custom tag decoders.
+    pub fn with_parser(mut self, parser: TrirkParser) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    /// Reads the next recorded message once it is due. The message's
+    /// [`TwitchMessage::received_at`] is the time it was recorded.
+    pub async fn read_next(&mut self) -> Result<TwitchMessage, TrirkError> {
+        self.next().await.unwrap_or_else(|| {
+            Err(TrirkError::new(
+                TrirkErrorKind::ConnectionClosed,
+                "end of the capture",
+            ))
+        })
+    }
+}
+
+/// Recorded messages, ending with the capture.
+impl Stream for ReplayConnection {
+    type Item = Result<TwitchMessage, TrirkError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(at) = self.records.front().map(|record| record.at) else {
+            return Poll::Ready(None);
+        };
+        let (start, first) = *self.started.get_or_insert((Instant::now(), at));
+        if self.speed.is_finite() {
+            let offset = Duration::from_millis(at.as_millis().saturating_sub(first.as_millis()));
+            // a very slow replay can push the message past what Instant holds
+            let due = Duration::try_from_secs_f64(offset.as_secs_f64() / self.speed)
+                .ok()
+                .and_then(|delay| start.checked_add(delay))
+                .unwrap_or_else(|| Instant::now() + FAR_FUTURE);
+            if due > Instant::now() {
+                let sleep = self
+                    .sleep
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(due)));
+                sleep.as_mut().reset(due);
+                ready!(sleep.as_mut().poll(cx));
+            }
+        }
+        let record = self.records.pop_front().expect("checked above");
+        let message = self
+            .parser
+            .parse(&record.line)
+            .map(|message| message.with_received_at(record.at))
+            .map_err(TrirkError::from);
+        Poll::Ready(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use parser::{CommandType, Timestamp};
+    use tokio::time::Instant;
+
+    use crate::error::TrirkErrorKind;
+
+    use super::{Direction, Record, ReplayConnection};
+
+    const CAPTURE: &str = "\
+1700000000000 > NICK trirk
+1700000000000 < :tmi.twitch.tv 001 trirk :Welcome, GLHF!
+1700000002000 < :tmi.twitch.tv ROOMSTATE #dallas
+1700000002500 > PRIVMSG #dallas :hi
+1700000003000 < PING :tmi.twitch.tv
+";
+
+    #[test]
+    fn should_parse_records() {
+        assert_eq!(
+            Some(Record {
+                at: Timestamp::from_millis(1700000002500).unwrap(),
+                direction: Direction::Outbound,
+                line: "PRIVMSG #dallas :hi".into(),
+            }),
+            Record::parse("1700000002500 > PRIVMSG #dallas :hi")
+        );
+        assert_eq!(None, Record::parse("PRIVMSG #dallas :hi"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_replay_inbound_lines_with_their_pacing() {
+        let start = Instant::now();
+        let mut replay = ReplayConnection::from_capture(CAPTURE)
+            .unwrap()
+            .with_speed(2.0)
+            .unwrap();
+        let welcome = replay.read_next().await.unwrap();
+        assert_eq!(&CommandType::Numeric(1), welcome.command().command());
+        assert_eq!(
+            &Timestamp::from_millis(1700000000000),
+            welcome.received_at()
+        );
+        let room_state = replay.read_next().await.unwrap();
+        assert_eq!(&CommandType::RoomState, room_state.command().command());
+        assert_eq!(Duration::from_secs(1), start.elapsed());
+        let ping = replay.next().await.unwrap().unwrap();
+        assert_eq!(&CommandType::Ping, ping.command().command());
+        assert_eq!(Duration::from_millis(1500), start.elapsed());
+        assert!(replay.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_replay_without_waiting() {
+        let replay = ReplayConnection::from_capture(CAPTURE)
+            .unwrap()
+            .with_speed(f64::INFINITY)
+            .unwrap();
+        assert_eq!(3, replay.count().await);
+    }
+
+    #[test]
+    fn should_reject_speeds_not_above_zero() {
+        for speed in [0.0, -1.0, f64::NAN, f64::NEG_INFINITY] {
+            let Err(err) = ReplayConnection::from_capture(CAPTURE)
+                .unwrap()
+                .with_speed(speed)
+            else {
+                panic!("{speed} should be rejected");
+            };
+            assert_eq!(&TrirkErrorKind::Config, err.kind());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_not_overflow_on_very_slow_replays() {
+        let mut replay = ReplayConnection::from_capture(CAPTURE)
+            .unwrap()
+            .with_speed(f64::MIN_POSITIVE)
+            .unwrap();
+        replay.read_next().await.unwrap();
+        let next = tokio::time::timeout(Duration::from_secs(3600), replay.read_next()).await;
+        assert!(next.is_err(), "the next message is due in ages");
+    }
+}
@@ -1,17 +1,297 @@
-#[derive(Clone)]
+use std::{env, path::PathBuf, time::Duration};
+
+use derive_builder::Builder;
+use parser::ChannelName;
+use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer};
+
+use crate::error::{TrirkError, TrirkErrorKind};
+
+use super::reconnect::ReconnectPolicy;
+
+const IRC_HOST: &str = "irc.chat.twitch.tv";
+const IRC_PORT: u16 = 6667;
+const IRC_TLS_PORT: u16 = 6697;
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Builder, Clone)]
+#[builder(setter(into), build_fn(validate = "Self::validate"))]
 pub struct TwitchConfig {
-    pub(super) channel: String,
-    pub(super) oauth: String,
     pub(super) nickname: String,
+    pub(super) oauth: String,
+    /// Channels joined when the connection opens. The first one is the
+    /// target of [`TwitchIrc::privmsg`](super::TwitchIrc::privmsg).
+    #[builder(default, setter(each(name = "channel")))]
+    pub(super) channels: Vec<ChannelName>,
+    #[builder(default = "IRC_HOST.into()")]
+    pub(super) host: String,
+    /// Defaults to 6697 with TLS and 6667 without.
+    #[builder(default)]
+    pub(super) port: Option<u16>,
+    /// On by default.
+    #[builder(default = "true")]
+    pub(super) tls: bool,
+    /// PEM encoded CA certificates trusted instead of the bundled web PKI
+    /// roots, e.g. to talk to a local TLS IRC stub. Must hold at least one
+    /// certificate.
+    #[builder(default)]
+    pub(super) ca_certificate: Option<Vec<u8>>,
+    /// `twitch.tv/*` capabilities requested on connect.
+    #[builder(
+        default = "vec![\"commands\".into(), \"membership\".into(), \"tags\".into()]",
+        setter(each(name = "capability", into))
+    )]
+    pub(super) capabilities: Vec<String>,
+    #[builder(default = "Duration::from_secs(10)")]
+    pub(super) connect_timeout: Duration,
+    #[builder(default = "Duration::from_secs(10)")]
+    pub(super) join_timeout: Duration,
+    /// How often the client PINGs the server, to measure the latency and
+    /// notice dead connections. Must be above zero.
+    #[builder(default = "PING_INTERVAL")]
+    pub(super) ping_interval: Duration,
+    /// How long a PING may go unanswered before reconnecting, whatever
+    /// else arrives meanwhile. Must be above zero and shorter than `ping_interval`.
+    #[builder(default = "PONG_TIMEOUT")]
+    pub(super) pong_timeout: Duration,
+    #[builder(default)]
+    pub(super) reconnect: ReconnectPolicy,
+    /// Appends every raw line sent and received to this file, see
+    /// [`ReplayConnection`](super::capture::ReplayConnection).
+    #[builder(default)]
+    pub(super) capture: Option<PathBuf>,
+    /// Most channels a [`TwitchPool`](super::pool::TwitchPool) joins on
+    /// one connection before opening another.
+    #[builder(default = "100")]
+    pub(super) channels_per_connection: usize,
 }
 
 impl TwitchConfig {
     #[inline(always)]
-    pub fn new<T: Into<String>>(nickname: T, oauth: T, channel: T) -> Self {
-        Self {
-            nickname: nickname.into(),
-            oauth: oauth.into(),
-            channel: channel.into(),
+    pub fn new<T: Into<String>>(nickname: T, oauth: T, channel: ChannelName) -> Self {
+        Self::builder()
+            .nickname(nickname)
+            .oauth(oauth)
+            .channel(channel)
+            .build()
+            .expect("nickname, oauth and channel are set")
+    }
+
+    pub fn builder() -> TwitchConfigBuilder {
+        TwitchConfigBuilder::default()
+    }
+
+    /// A builder needing no credentials, for
+    /// [`TwitchIrc::anonymous`](super::TwitchIrc::anonymous).
+    pub fn anonymous() -> TwitchConfigBuilder {
+        let mut builder = Self::builder();
+        builder.nickname(anonymous_nickname()).oauth("");
+        builder
+    }
+
+    /// Replaces the credentials with the read-only `justinfan<random>`
+    /// login Twitch grants without an oauth token.
+    pub(super) fn into_anonymous(mut self) -> Self {
+        self.nickname = anonymous_nickname();
+        self.oauth.clear();
+        self
+    }
+
+    /// Reads the configuration from `TRIRK_*` environment variables.
+    ///
+    /// `TRIRK_NICKNAME`, `TRIRK_OAUTH` and `TRIRK_CHANNEL` (a comma separated
+    /// list) are required; `TRIRK_HOST`, `TRIRK_PORT`, `TRIRK_TLS`,
+    /// `TRIRK_CA_FILE`, `TRIRK_CAPABILITIES` and `TRIRK_CAPTURE` are optional. TLS
+    /// is on unless `TRIRK_TLS` is `0` or `false`.
+    pub fn from_env() -> Result<Self, TrirkError> {
+        let mut builder = Self::builder();
+        builder
+            .nickname(required_var("TRIRK_NICKNAME")?)
+            .oauth(required_var("TRIRK_OAUTH")?);
+        builder.channels(channels_var(&required_var("TRIRK_CHANNEL")?)?);
+        if let Ok(host) = env::var("TRIRK_HOST") {
+            builder.host(host);
+        }
+        if let Ok(port) = env::var("TRIRK_PORT") {
+            let port = port.parse::<u16>().map_err(|err| {
+                TrirkError::with_source(TrirkErrorKind::Config, format!("TRIRK_PORT: {err}"), err)
+            })?;
+            builder.port(port);
+        }
+        let tls = env::var("TRIRK_TLS").unwrap_or_default();
+        builder.tls(!matches!(tls.as_str(), "0" | "false"));
+        if let Ok(path) = env::var("TRIRK_CA_FILE") {
+            let pem = std::fs::read(&path).map_err(|err| {
+                TrirkError::with_source(
+                    TrirkErrorKind::Config,
+                    format!("TRIRK_CA_FILE {path}: {err}"),
+                    err,
+                )
+            })?;
+            builder.ca_certificate(pem);
+        }
+        if let Ok(path) = env::var("TRIRK_CAPTURE") {
+            builder.capture(PathBuf::from(path));
+        }
+        if let Ok(capabilities) = env::var("TRIRK_CAPABILITIES") {
+            builder.capabilities(
+                capabilities
+                    .split(',')
+                    .filter(|capability| !capability.is_empty())
+                    .map(String::from)
+                    .collect::<Vec<_>>(),
+            );
+        }
+        builder
+            .build()
+            .map_err(|err| TrirkError::with_source(TrirkErrorKind::Config, err.to_string(), err))
+    }
+
+    pub(super) fn port(&self) -> u16 {
+        match (self.port, self.tls) {
+            (Some(port), _) => port,
+            (None, true) => IRC_TLS_PORT,
+            (None, false) => IRC_PORT,
+        }
+    }
+}
+
+impl TwitchConfigBuilder {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(Some(pem)) = &self.ca_certificate {
+            let mut certificates = CertificateDer::pem_slice_iter(pem).peekable();
+            if certificates.peek().is_none() {
+                return Err("ca_certificate holds no PEM certificate".into());
+            }
+            for certificate in certificates {
+                certificate.map_err(|err| format!("ca_certificate: {err}"))?;
+            }
         }
+        let ping_interval = self.ping_interval.unwrap_or(PING_INTERVAL);
+        let pong_timeout = self.pong_timeout.unwrap_or(PONG_TIMEOUT);
+        if ping_interval.is_zero() || pong_timeout.is_zero() {
+            return Err("ping_interval and pong_timeout must not be zero".into());
+        }
+        if pong_timeout >= ping_interval {
+            return Err(format!(
+                "pong_timeout ({pong_timeout:?}) must be shorter than ping_interval ({ping_interval:?})"
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn anonymous_nickname() -> String {
+    format!("justinfan{}", fastrand::u32(10_000..100_000_000))
+}
+
+fn required_var(name: &str) -> Result<String, TrirkError> {
+    env::var(name)
+        .map_err(|_| TrirkError::new(TrirkErrorKind::Config, format!("{name} is not set")))
+}
+
+/// The comma separated channels of `TRIRK_CHANNEL`, spaces around them
+/// ignored.
+fn channels_var(value: &str) -> Result<Vec<ChannelName>, TrirkError> {
+    value
+        .split(',')
+        .map(|channel| {
+            ChannelName::new(channel.trim()).map_err(|err| {
+                TrirkError::with_source(
+                    TrirkErrorKind::Config,
+                    format!("TRIRK_CHANNEL: {err}"),
+                    err,
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use parser::ChannelName;
+
+    use crate::error::TrirkErrorKind;
+
+    use super::{channels_var, TwitchConfig};
+
+    #[test]
+    fn should_build_with_defaults() {
+        let config = TwitchConfig::new("trirk", "oauth:token", ChannelName::new("dallas").unwrap());
+        assert_eq!("irc.chat.twitch.tv", config.host);
+        assert!(config.tls);
+        assert_eq!(6697, config.port());
+        assert_eq!(vec!["commands", "membership", "tags"], config.capabilities);
+    }
+
+    #[test]
+    fn should_override_endpoint_and_capabilities() {
+        let config = TwitchConfig::builder()
+            .nickname("trirk")
+            .oauth("oauth:token")
+            .host("localhost")
+            .tls(false)
+            .capability("tags")
+            .build()
+            .unwrap();
+        assert_eq!("localhost", config.host);
+        assert_eq!(6667, config.port());
+        assert_eq!(vec!["tags"], config.capabilities);
+        assert!(config.channels.is_empty());
+    }
+
+    #[test]
+    fn should_require_credentials() {
+        assert!(TwitchConfig::builder().nickname("trirk").build().is_err());
+    }
+
+    #[test]
+    fn should_reject_a_ca_without_certificates() {
+        for pem in [&b""[..], b"not a certificate"] {
+            let err = TwitchConfig::builder()
+                .nickname("trirk")
+                .oauth("oauth:token")
+                .ca_certificate(pem.to_vec())
+                .build()
+                .err()
+                .unwrap();
+            assert!(err.to_string().contains("no PEM certificate"));
+        }
+    }
+
+    #[test]
+    fn should_reject_keepalive_timings_that_would_flood_the_server() {
+        let build = |ping_interval: u64, pong_timeout: u64| {
+            TwitchConfig::builder()
+                .nickname("trirk")
+                .oauth("oauth:token")
+                .ping_interval(Duration::from_secs(ping_interval))
+                .pong_timeout(Duration::from_secs(pong_timeout))
+                .build()
+        };
+        assert!(build(60, 10).is_ok());
+        assert!(build(0, 10).is_err());
+        assert!(build(60, 0).is_err());
+        let err = build(10, 10).err().unwrap();
+        assert!(
+            err.to_string().contains("shorter than ping_interval"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn should_read_channels_separated_by_commas_and_spaces() {
+        assert_eq!(
+            vec![
+                ChannelName::new("a").unwrap(),
+                ChannelName::new("b").unwrap()
+            ],
+            channels_var("a, b").unwrap()
+        );
+        let err = channels_var("a,,b").unwrap_err();
+        assert_eq!(&TrirkErrorKind::Config, err.kind());
+        assert!(err.to_string().contains("TRIRK_CHANNEL"), "{err}");
     }
 }
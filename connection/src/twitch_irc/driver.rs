@@ -0,0 +1,839 @@
+use std::{collections::VecDeque, io, sync::MutexGuard, time::Duration};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    StreamExt,
+};
+use parser::{
+    trirk_parser::{line_buffer::LineBuffer, TrirkParser},
+    ChannelName, CommandType, NoticeKind, Timestamp, TwitchMessage,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{broadcast, watch},
+    time::{sleep_until, timeout_at, Instant},
+};
+use tracing::{debug, error, field, info, trace, warn};
+
+use crate::error::{TrirkError, TrirkErrorKind};
+
+use super::{
+    capture::Recorder,
+    config::TwitchConfig,
+    handshake::establish,
+    keepalive::{Expired, Keepalive, KEEPALIVE_TOKEN},
+    outgoing::{self, Outgoing, MAX_MESSAGE_LENGTH},
+    rate_limit::{RateLimiter, SharedLimiter},
+    reconnect::{notify, ConnectionEvent},
+    transport::IrcStream,
+};
+
+/// Echoed back to a PING that names no server.
+const TWITCH_SERVER: &str = "tmi.twitch.tv";
+/// NOTICE `msg-id`s Twitch answers a JOIN with when the channel can't be joined.
+const JOIN_REJECTIONS: [&str; 4] = [
+    "msg_channel_suspended",
+    "msg_banned",
+    "tos_ban",
+    "msg_room_not_found",
+];
+
+pub(super) type Incoming = Result<TwitchMessage, TrirkError>;
+type JoinReply = oneshot::Sender<Result<(), TrirkError>>;
+
+pub(super) enum Request {
+    Send(Outgoing),
+    /// A join answered once Twitch confirms or refuses it.
+    Join(ChannelName, JoinReply),
+}
+
+/// What the driver shares with the handles, see [`TwitchIrc::channels`](super::TwitchIrc::channels).
+#[derive(Clone, Debug, Default)]
+pub(super) struct Status {
+    pub(super) channels: Vec<ChannelName>,
+    pub(super) latency: Option<Duration>,
+}
+
+struct PendingJoin {
+    channel: ChannelName,
+    deadline: Instant,
+    reply: Option<JoinReply>,
+}
+
+enum Wake {
+    Read(Result<io::Result<usize>, tokio::time::error::Elapsed>),
+    Request(Option<Request>),
+    Message,
+    Join,
+    JoinDeadline,
+}
+
+/// Owns the socket: reads and parses incoming lines, keeps the connection
+/// alive, and writes the requests of every handle in their rate limited
+/// order.
+pub(super) struct Driver {
+    configuration: TwitchConfig,
+    parser: TrirkParser,
+    lifecycle: broadcast::Sender<ConnectionEvent>,
+    stream: Box<dyn IrcStream>,
+    lines: LineBuffer,
+    channels: Vec<ChannelName>,
+    limiter: SharedLimiter,
+    keepalive: Keepalive,
+    /// PRIVMSG lines waiting for the rate limiter, in order within each
+    /// channel, see [`Driver::next_message`].
+    messages: VecDeque<(ChannelName, String)>,
+    /// Channels waiting for a JOIN slot.
+    joins: VecDeque<(ChannelName, Option<JoinReply>)>,
+    /// JOINs sent and waiting for their ROOMSTATE.
+    joining: Vec<PendingJoin>,
+    incoming: mpsc::UnboundedSender<Incoming>,
+    status: watch::Sender<Status>,
+    recorder: Option<Recorder>,
+}
+
+impl Driver {
+    pub(super) fn new(
+        configuration: TwitchConfig,
+        parser: TrirkParser,
+        lifecycle: broadcast::Sender<ConnectionEvent>,
+        (stream, lines): (Box<dyn IrcStream>, LineBuffer),
+        incoming: mpsc::UnboundedSender<Incoming>,
+        status: watch::Sender<Status>,
+        recorder: Option<Recorder>,
+    ) -> Self {
+        let keepalive = Keepalive::new(configuration.ping_interval, configuration.pong_timeout);
+        // configured channels count as joined once Twitch confirms them
+        let joins = configuration
+            .channels
+            .iter()
+            .map(|channel| (channel.clone(), None))
+            .collect();
+        Self {
+            channels: vec![],
+            configuration,
+            parser,
+            lifecycle,
+            stream,
+            lines,
+            limiter: RateLimiter::shared(),
+            keepalive,
+            messages: VecDeque::new(),
+            joins,
+            joining: vec![],
+            incoming,
+            status,
+            recorder,
+        }
+    }
+
+    /// Shares the limits of the connections logged in to the same account.
+    pub(super) fn with_limiter(mut self, limiter: SharedLimiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    fn limiter(&self) -> MutexGuard<'_, RateLimiter> {
+        self.limiter.lock().unwrap()
+    }
+
+    /// Runs until every request sender is dropped, sending the messages
+    /// still queued, or until reconnecting fails, the latter reported as
+    /// the last incoming item.
+    pub(super) async fn run(mut self, mut requests: mpsc::Receiver<Request>) {
+        let mut buffer = [0; 1024];
+        loop {
+            // lines read during the handshake or along with the last read
+            if let Err(err) = self.read_lines().await {
+                self.fail(err);
+                return;
+            }
+            let now = Instant::now();
+            let message_at = self.next_message().map(|(_, available_at)| available_at);
+            let join_at = (!self.joins.is_empty()).then(|| self.limiter().join_available_at());
+            let join_deadline = self.joining.iter().map(|join| join.deadline).min();
+            let read = timeout_at(self.keepalive.deadline(), self.stream.read(&mut buffer));
+            let wake = tokio::select! {
+                read = read => Wake::Read(read),
+                request = requests.next() => Wake::Request(request),
+                _ = sleep_until(message_at.unwrap_or(now)), if message_at.is_some() => Wake::Message,
+                _ = sleep_until(join_at.unwrap_or(now)), if join_at.is_some() => Wake::Join,
+                _ = sleep_until(join_deadline.unwrap_or(now)), if join_deadline.is_some() => {
+                    Wake::JoinDeadline
+                }
+            };
+            let result = match wake {
+                Wake::Read(Err(_)) => self.keepalive().await,
+                Wake::Read(Ok(Ok(0))) => self.reconnect("connection closed by server".into()).await,
+                Wake::Read(Ok(Ok(size))) => {
+                    if let Err(err) = self.lines.extend(&buffer[..size]) {
+                        warn!(error = %err, "oversized line");
+                        self.forward(Err(err.into()));
+                    }
+                    Ok(())
+                }
+                Wake::Read(Ok(Err(err))) => self.reconnect(err.to_string()).await,
+                Wake::Request(Some(request)) => self.handle(request).await,
+                Wake::Request(None) => {
+                    self.flush().await;
+                    return;
+                }
+                Wake::Message => self.send_message().await,
+                Wake::Join => self.send_join().await,
+                Wake::JoinDeadline => {
+                    self.expire_joins();
+                    Ok(())
+                }
+            };
+            if let Err(err) = result {
+                self.fail(err);
+                return;
+            }
+        }
+    }
+
+    fn fail(&self, err: TrirkError) {
+        if self.abandoned() {
+            debug!(error = %err, "every handle dropped, closing");
+            return;
+        }
+        error!(error = %err, "connection closed for good");
+        self.forward(Err(err));
+    }
+
+    /// Whether the reader and every sender are gone, which the request
+    /// stream only tells once it is polled again.
+    fn abandoned(&self) -> bool {
+        self.incoming.is_closed() && self.status.is_closed()
+    }
+
+    async fn read_lines(&mut self) -> Result<(), TrirkError> {
+        while let Some(line) = self.lines.next_line() {
+            if line.is_empty() {
+                continue;
+            }
+            let received_at = Timestamp::now();
+            let message = match self.parser.parse_bytes(&line) {
+                Ok(message) => message,
+                Err(err) => {
+                    warn!(error = %err, line = %String::from_utf8_lossy(&line), "unparsable line");
+                    self.forward(Err(err.into()));
+                    continue;
+                }
+            };
+            trace!(
+                channel = message.command().channel().as_ref().map(field::display),
+                command = ?message.command().command(),
+                "received"
+            );
+            if message.command().command() == &CommandType::Pong
+                && message.parameters().as_deref() == Some(KEEPALIVE_TOKEN)
+                && self.keepalive.pong()
+            {
+                debug!(latency = ?self.keepalive.latency(), "keepalive answered");
+                self.publish();
+                continue;
+            }
+            if message.command().command() == &CommandType::Ping {
+                // Twitch closes connections that leave its PINGs unanswered
+                let server = message.parameters().as_deref().unwrap_or(TWITCH_SERVER);
+                let pong = format!("PONG :{}\r\n", outgoing::sanitize(server));
+                self.write_or_reconnect(pong.as_bytes()).await?;
+            }
+            self.limiter().observe(&message);
+            self.confirm_join(&message);
+            if let Some(err) = rate_limited(&message) {
+                debug!(error = %err, "message dropped by Twitch");
+                self.forward(Err(err));
+            }
+            let reconnect = message.command().command() == &CommandType::Reconnect;
+            self.forward(Ok(message.with_received_at(received_at)));
+            if reconnect {
+                return self.reconnect("server requested a reconnect".into()).await;
+            }
+        }
+        Ok(())
+    }
+
+    fn forward(&self, item: Incoming) {
+        // the reader half may be gone while senders are still in use
+        let _ = self.incoming.unbounded_send(item);
+    }
+
+    fn publish(&self) {
+        self.status.send_replace(Status {
+            channels: self.channels.clone(),
+            latency: self.keepalive.latency(),
+        });
+    }
+
+    async fn handle(&mut self, request: Request) -> Result<(), TrirkError> {
+        match request {
+            Request::Send(Outgoing::Privmsg { channel, text }) => {
+                let text = outgoing::sanitize(&text);
+                let chunks = outgoing::split(&text, MAX_MESSAGE_LENGTH);
+                debug!(%channel, chunks = chunks.len(), "queued message");
+                for chunk in chunks {
+                    let line = format!("PRIVMSG {channel} :{chunk}\r\n");
+                    self.messages.push_back((channel.clone(), line));
+                }
+                Ok(())
+            }
+            Request::Send(Outgoing::Join(channel)) => {
+                self.queue_join(channel, None);
+                Ok(())
+            }
+            Request::Join(channel, reply) => {
+                self.queue_join(channel, Some(reply));
+                Ok(())
+            }
+            Request::Send(Outgoing::Part(channel)) => {
+                self.channels.retain(|joined| joined != &channel);
+                self.cancel_join(&channel);
+                self.publish();
+                info!(%channel, "parted");
+                self.write_or_reconnect(format!("PART {channel}\r\n").as_bytes())
+                    .await
+            }
+            Request::Send(Outgoing::Raw(bytes)) => self.write_or_reconnect(&bytes).await,
+        }
+    }
+
+    fn queue_join(&mut self, channel: ChannelName, reply: Option<JoinReply>) {
+        if self.channels.contains(&channel) {
+            if let Some(reply) = reply {
+                let _ = reply.send(Ok(()));
+            }
+            return;
+        }
+        // already on its way, e.g. rejoining after a reconnect
+        let unanswered = self
+            .joins
+            .iter_mut()
+            .map(|(queued, reply)| (&*queued, reply))
+            .chain(
+                self.joining
+                    .iter_mut()
+                    .map(|join| (&join.channel, &mut join.reply)),
+            )
+            .find(|(queued, waiting)| *queued == &channel && waiting.is_none());
+        if let Some((_, waiting)) = unanswered {
+            *waiting = reply;
+            return;
+        }
+        self.joins.push_back((channel, reply));
+    }
+
+    /// Drops the queued or pending join of a parted `channel`, so its
+    /// ROOMSTATE does not count it as joined again.
+    fn cancel_join(&mut self, channel: &ChannelName) {
+        let mut cancelled = vec![];
+        self.joins.retain_mut(|(queued, reply)| {
+            if queued != channel {
+                return true;
+            }
+            cancelled.extend(reply.take());
+            false
+        });
+        self.joining.retain_mut(|join| {
+            if &join.channel != channel {
+                return true;
+            }
+            cancelled.extend(join.reply.take());
+            false
+        });
+        for reply in cancelled {
+            let _ = reply.send(Err(TrirkError::new(
+                TrirkErrorKind::JoinRejected,
+                format!("{channel} was parted before the join completed"),
+            )));
+        }
+    }
+
+    /// Position of the queued message sent next and when the limiter
+    /// allows it. Only the first message of each channel is a candidate,
+    /// so a channel in slow mode holds back its own messages but not those
+    /// of other channels.
+    fn next_message(&self) -> Option<(usize, Instant)> {
+        let limiter = self.limiter();
+        let mut seen: Vec<&ChannelName> = vec![];
+        let mut next: Option<(usize, Instant)> = None;
+        for (index, (channel, _)) in self.messages.iter().enumerate() {
+            if seen.contains(&channel) {
+                continue;
+            }
+            seen.push(channel);
+            let available_at = limiter.message_available_at(channel);
+            match next {
+                Some((_, earliest)) if earliest <= available_at => {}
+                _ => next = Some((index, available_at)),
+            }
+        }
+        next
+    }
+
+    async fn send_message(&mut self) -> Result<(), TrirkError> {
+        let Some((index, _)) = self.next_message() else {
+            return Ok(());
+        };
+        let (channel, line) = self
+            .messages
+            .remove(index)
+            .expect("index of a queued message");
+        if !self.limiter().try_acquire_message(&channel) {
+            // another connection of the account took the slot
+            self.messages.insert(index, (channel, line));
+            return Ok(());
+        }
+        debug!(%channel, "sending message");
+        if let Err(err) = self.write(line.as_bytes()).await {
+            // sent again once reconnected
+            self.messages.insert(index, (channel, line));
+            return self.reconnect(err.to_string()).await;
+        }
+        Ok(())
+    }
+
+    /// Sends the messages still queued once every handle is dropped, their
+    /// senders were told they would go out. Queued joins are dropped with
+    /// nobody left to use the channels.
+    async fn flush(&mut self) {
+        debug!(
+            messages = self.messages.len(),
+            joins = self.joins.len(),
+            "every handle dropped, sending the queued messages before closing"
+        );
+        while let Some((_, available_at)) = self.next_message() {
+            sleep_until(available_at).await;
+            if let Err(err) = self.send_message().await {
+                warn!(error = %err, discarded = self.messages.len(), "queued messages not sent");
+                return;
+            }
+        }
+    }
+
+    async fn send_join(&mut self) -> Result<(), TrirkError> {
+        let Some((channel, reply)) = self.joins.pop_front() else {
+            return Ok(());
+        };
+        if !self.limiter().try_acquire_join() {
+            // another connection of the account took the slot
+            self.joins.push_front((channel, reply));
+            return Ok(());
+        }
+        debug!(%channel, "joining");
+        if let Err(err) = self.write(format!("JOIN {channel}\r\n").as_bytes()).await {
+            // sent again once reconnected
+            self.joins.push_front((channel, reply));
+            return self.reconnect(err.to_string()).await;
+        }
+        self.joining.push(PendingJoin {
+            channel,
+            deadline: Instant::now() + self.configuration.join_timeout,
+            reply,
+        });
+        Ok(())
+    }
+
+    /// Resolves the pending join `message` confirms (ROOMSTATE) or refuses
+    /// (one of [`JOIN_REJECTIONS`]).
+    fn confirm_join(&mut self, message: &TwitchMessage) {
+        let Some(channel) = message.command().channel() else {
+            return;
+        };
+        let result = match message.command().command() {
+            CommandType::RoomState => Ok(()),
+            CommandType::Notice => {
+                let message_id = message
+                    .tags()
+                    .as_ref()
+                    .map_or("", |tags| tags.message_id().as_str());
+                if !JOIN_REJECTIONS.contains(&message_id) {
+                    return;
+                }
+                Err(TrirkError::new(
+                    TrirkErrorKind::JoinRejected,
+                    format!(
+                        "could not join {channel}: {}",
+                        message.parameters().as_deref().unwrap_or(message_id)
+                    ),
+                ))
+            }
+            _ => return,
+        };
+        let Some(position) = self
+            .joining
+            .iter()
+            .position(|join| &join.channel == channel)
+        else {
+            return;
+        };
+        let join = self.joining.swap_remove(position);
+        match &result {
+            Ok(()) => {
+                info!(channel = %join.channel, "joined");
+                self.channels.push(join.channel);
+                self.publish();
+            }
+            Err(err) => warn!(channel = %join.channel, error = %err, "join rejected"),
+        }
+        if let Some(reply) = join.reply {
+            let _ = reply.send(result);
+        }
+    }
+
+    fn expire_joins(&mut self) {
+        let now = Instant::now();
+        let (expired, joining) = self
+            .joining
+            .drain(..)
+            .partition::<Vec<_>, _>(|join| join.deadline <= now);
+        self.joining = joining;
+        for join in expired {
+            warn!(channel = %join.channel, "no ROOMSTATE after joining");
+            if let Some(reply) = join.reply {
+                let _ = reply.send(Err(TrirkError::new(
+                    TrirkErrorKind::Timeout,
+                    format!("no ROOMSTATE received after joining {}", join.channel),
+                )));
+            }
+        }
+    }
+
+    /// Writes `bytes`. An error leaves the socket broken: the caller
+    /// queues again what must reach Twitch and reconnects.
+    async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        trace!(line = %String::from_utf8_lossy(bytes).trim_end(), "sent");
+        self.stream.write_all(bytes).await?;
+        // TLS keeps what the socket did not take in its session until flushed
+        self.stream.flush().await
+    }
+
+    /// Writes `bytes` meaningful only on this socket, e.g. a PONG,
+    /// reconnecting without them when it is broken.
+    async fn write_or_reconnect(&mut self, bytes: &[u8]) -> Result<(), TrirkError> {
+        match self.write(bytes).await {
+            Ok(()) => Ok(()),
+            Err(err) => self.reconnect(err.to_string()).await,
+        }
+    }
+
+    async fn keepalive(&mut self) -> Result<(), TrirkError> {
+        match self.keepalive.expired() {
+            Expired::SendPing => {
+                debug!("sending keepalive PING");
+                let ping = format!("PING :{KEEPALIVE_TOKEN}\r\n");
+                self.write_or_reconnect(ping.as_bytes()).await
+            }
+            Expired::Dead => self.reconnect("keepalive PING unanswered".into()).await,
+        }
+    }
+
+    async fn reconnect(&mut self, reason: String) -> Result<(), TrirkError> {
+        warn!(%reason, "disconnected");
+        notify(&self.lifecycle, ConnectionEvent::Disconnected { reason });
+        // joins in flight are sent again on the new connection
+        for join in self.joining.drain(..).rev() {
+            self.joins.push_front((join.channel, join.reply));
+        }
+        let mut attempt = 0;
+        let mut last_error: Option<TrirkError> = None;
+        loop {
+            attempt += 1;
+            let Some(delay) = self.configuration.reconnect.delay(attempt) else {
+                let message = format!("could not reconnect after {} attempts", attempt - 1);
+                return Err(match last_error {
+                    Some(err) => {
+                        TrirkError::with_source(TrirkErrorKind::ConnectionClosed, message, err)
+                    }
+                    None => TrirkError::new(TrirkErrorKind::ConnectionClosed, message),
+                });
+            };
+            info!(attempt, ?delay, "reconnecting");
+            notify(
+                &self.lifecycle,
+                ConnectionEvent::Reconnecting {
+                    attempt,
+                    delay,
+                    last_error: last_error.as_ref().map(ToString::to_string),
+                },
+            );
+            tokio::time::sleep(delay).await;
+            if self.abandoned() {
+                return Err(TrirkError::new(
+                    TrirkErrorKind::ConnectionClosed,
+                    "every handle dropped while reconnecting",
+                ));
+            }
+            let (stream, lines) =
+                match establish(&self.configuration, &self.parser, self.recorder.as_ref()).await {
+                    Ok(established) => established,
+                    // retrying won't fix the token or the requested capabilities
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            TrirkErrorKind::Auth | TrirkErrorKind::CapabilityRejected
+                        ) =>
+                    {
+                        return Err(err)
+                    }
+                    Err(err) => {
+                        warn!(attempt, error = %err, "reconnect attempt failed");
+                        last_error = Some(err);
+                        continue;
+                    }
+                };
+            self.stream = stream;
+            self.lines = lines;
+            self.keepalive.reset();
+            self.rejoin_all();
+            info!("reconnected");
+            notify(&self.lifecycle, ConnectionEvent::Connected);
+            return Ok(());
+        }
+    }
+
+    /// Queues a JOIN for every channel of [`Driver::channels`] after a
+    /// reconnect. The run loop sends them within the join limit and they
+    /// count as joined again once confirmed.
+    fn rejoin_all(&mut self) {
+        for channel in self.channels.drain(..).rev() {
+            self.joins.push_front((channel, None));
+        }
+        self.publish();
+    }
+}
+
+/// The error standing for a NOTICE telling a PRIVMSG was dropped for being
+/// sent too quickly. The NOTICE itself is still forwarded after it.
+fn rate_limited(message: &TwitchMessage) -> Option<TrirkError> {
+    if message.command().command() != &CommandType::Notice {
+        return None;
+    }
+    let message_id = message.tags().as_ref()?.message_id().as_str();
+    match NoticeKind::from(message_id) {
+        NoticeKind::RateLimited | NoticeKind::SlowMode => {
+            let text = message.parameters().as_deref().unwrap_or(message_id);
+            let message = match message.command().channel() {
+                Some(channel) => format!("{channel}: {text}"),
+                None => text.into(),
+            };
+            Some(TrirkError::new(TrirkErrorKind::RateLimited, message))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+    };
+
+    use futures::{
+        channel::{mpsc, oneshot},
+        SinkExt, StreamExt,
+    };
+    use mock_server::{MockServer, Script};
+    use parser::{trirk_parser::TrirkParser, ChannelName, CommandType};
+    use tokio::{
+        io::{AsyncRead, AsyncWrite, ReadBuf},
+        sync::{broadcast, watch},
+        task::JoinHandle,
+    };
+
+    use crate::twitch_irc::{
+        handshake::establish, outgoing::Outgoing, tests::local_config, transport::IrcStream,
+    };
+
+    use super::{Driver, RateLimiter, Request, SharedLimiter, Status};
+
+    /// Reads through to the mock server but fails every write once broken,
+    /// like a socket dropped while sending.
+    struct Breakable {
+        inner: Box<dyn IrcStream>,
+        broken: Arc<AtomicBool>,
+    }
+
+    impl AsyncRead for Breakable {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Breakable {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            if this.broken.load(Ordering::SeqCst) {
+                return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+            }
+            Pin::new(&mut this.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+        }
+    }
+
+    /// Drives a connection to `server` that joined #dallas, limited by
+    /// `limiter`. The flag breaks its socket for writing, the task ends
+    /// with the driver.
+    async fn driver(
+        server: &MockServer,
+        limiter: SharedLimiter,
+    ) -> (mpsc::Sender<Request>, Arc<AtomicBool>, JoinHandle<()>) {
+        let configuration = local_config(server);
+        let (stream, lines) = establish(&configuration, &TrirkParser::new(), None)
+            .await
+            .unwrap();
+        let broken = Arc::new(AtomicBool::new(false));
+        let stream = Box::new(Breakable {
+            inner: stream,
+            broken: broken.clone(),
+        });
+        let (incoming, mut reader) = mpsc::unbounded();
+        let (requests, requests_receiver) = mpsc::channel(16);
+        let driver = Driver::new(
+            configuration,
+            TrirkParser::new(),
+            broadcast::channel(16).0,
+            (stream, lines),
+            incoming,
+            watch::channel(Status::default()).0,
+            None,
+        )
+        .with_limiter(limiter);
+        let task = tokio::spawn(driver.run(requests_receiver));
+        while let Some(message) = reader.next().await {
+            if message.unwrap().command().command() == &CommandType::RoomState {
+                break;
+            }
+        }
+        // keeps the driver forwarding once the test stops reading
+        tokio::spawn(reader.for_each(|_| async {}));
+        (requests, broken, task)
+    }
+
+    async fn broken_driver(server: &MockServer) -> mpsc::Sender<Request> {
+        let (requests, broken, _) = driver(server, RateLimiter::shared()).await;
+        broken.store(true, Ordering::SeqCst);
+        requests
+    }
+
+    fn privmsg(channel: &str, text: &str) -> Request {
+        Request::Send(Outgoing::Privmsg {
+            channel: ChannelName::new(channel).unwrap(),
+            text: text.into(),
+        })
+    }
+
+    fn received(server: &MockServer, line: &str) -> usize {
+        server
+            .received()
+            .iter()
+            .filter(|received| *received == line)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn should_send_a_message_again_after_a_failed_write() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let mut requests = broken_driver(&server).await;
+        requests.send(privmsg("dallas", "hi")).await.unwrap();
+        server.wait_for("PRIVMSG #dallas :hi").await;
+        assert_eq!(2, server.connections());
+        assert_eq!(1, received(&server, "PRIVMSG #dallas :hi"));
+    }
+
+    #[tokio::test]
+    async fn should_send_queued_messages_after_every_handle_is_dropped() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let (mut requests, _, task) = driver(&server, RateLimiter::shared()).await;
+        // the regular limit lets 20 through, the rest waits 30 seconds
+        tokio::time::pause();
+        for number in 0..25 {
+            requests
+                .send(privmsg("dallas", &number.to_string()))
+                .await
+                .unwrap();
+        }
+        drop(requests);
+        task.await.unwrap();
+        tokio::time::resume();
+        server
+            .wait_until(|received| {
+                received
+                    .iter()
+                    .filter(|line| line.starts_with("PRIVMSG"))
+                    .count()
+                    == 25
+            })
+            .await;
+        for number in 0..25 {
+            assert_eq!(1, received(&server, &format!("PRIVMSG #dallas :{number}")));
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_hold_back_other_channels_behind_slow_mode() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let limiter = RateLimiter::shared();
+        let ronni = ChannelName::new("ronni").unwrap();
+        {
+            let mut limiter = limiter.lock().unwrap();
+            limiter.observe(
+                &TrirkParser::new()
+                    .parse("@slow=120 :tmi.twitch.tv ROOMSTATE #ronni")
+                    .unwrap(),
+            );
+            assert!(limiter.try_acquire_message(&ronni));
+        }
+        let (mut requests, _, _) = driver(&server, limiter).await;
+        for request in [
+            privmsg("ronni", "slow"),
+            privmsg("dallas", "one"),
+            privmsg("dallas", "two"),
+        ] {
+            requests.send(request).await.unwrap();
+        }
+        server.wait_for("PRIVMSG #dallas :two").await;
+        let sent = server
+            .received()
+            .into_iter()
+            .filter(|line| line.starts_with("PRIVMSG"))
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["PRIVMSG #dallas :one", "PRIVMSG #dallas :two"], sent);
+    }
+
+    #[tokio::test]
+    async fn should_send_a_join_again_after_a_failed_write() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let mut requests = broken_driver(&server).await;
+        let (reply, joined) = oneshot::channel();
+        requests
+            .send(Request::Join(ChannelName::new("ronni").unwrap(), reply))
+            .await
+            .unwrap();
+        joined.await.unwrap().unwrap();
+        assert_eq!(2, server.connections());
+        assert_eq!(1, received(&server, "JOIN #ronni"));
+    }
+}
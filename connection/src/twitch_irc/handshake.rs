@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+
+use parser::{
+    trirk_parser::{line_buffer::LineBuffer, TrirkParser},
+    CommandType, TwitchMessage,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, warn};
+
+use crate::error::{TrirkError, TrirkErrorKind};
+
+use super::{
+    capture::{Recorder, Recording},
+    config::TwitchConfig,
+    transport::{connect, IrcStream},
+};
+
+const CAP_REQ: &str = "CAP REQ :twitch.tv/";
+const CAPABILITY_PREFIX: &str = "twitch.tv/";
+/// NOTICE texts Twitch answers a bad PASS with before closing the socket.
+const AUTH_FAILURES: [&str; 2] = ["Login authentication failed", "Improperly formatted auth"];
+
+/// Server replies the handshake waits for.
+#[derive(Debug, PartialEq, Eq)]
+enum Reply<'a> {
+    Welcome,
+    Acknowledged(Vec<&'a str>),
+    Rejected(Vec<&'a str>),
+    AuthFailed(&'a str),
+}
+
+/// Opens the socket, authenticates and requests the capabilities, then
+/// waits until Twitch welcomes us (001) and acknowledges every capability.
+/// Returns the lines read meanwhile so they still reach the reader.
+pub(super) async fn establish(
+    configuration: &TwitchConfig,
+    parser: &TrirkParser,
+    recorder: Option<&Recorder>,
+) -> Result<(Box<dyn IrcStream>, LineBuffer), TrirkError> {
+    tokio::time::timeout(
+        configuration.connect_timeout,
+        handshake(configuration, parser, recorder),
+    )
+    .await
+    .map_err(|elapsed| {
+        TrirkError::with_source(
+            TrirkErrorKind::Timeout,
+            "no welcome from the IRC server in time",
+            elapsed,
+        )
+    })?
+}
+
+#[tracing::instrument(skip_all)]
+async fn handshake(
+    configuration: &TwitchConfig,
+    parser: &TrirkParser,
+    recorder: Option<&Recorder>,
+) -> Result<(Box<dyn IrcStream>, LineBuffer), TrirkError> {
+    let mut connection = connect(configuration).await?;
+    if let Some(recorder) = recorder {
+        connection = Box::new(Recording::new(connection, recorder.clone()));
+    }
+    let mut handshake = String::new();
+    // only anonymous logins have no token, TwitchIrc::new refuses it
+    if !configuration.oauth.is_empty() {
+        handshake.push_str(&format!("PASS {}\r\n", configuration.oauth));
+    }
+    handshake.push_str(&format!("NICK {}\r\n", configuration.nickname));
+    for capability in &configuration.capabilities {
+        handshake.push_str(&format!("{CAP_REQ}{capability}\r\n"));
+    }
+    connection.write_all(handshake.as_bytes()).await?;
+    connection.flush().await?;
+
+    let mut pending: HashSet<&str> = configuration
+        .capabilities
+        .iter()
+        .map(String::as_str)
+        .collect();
+    let mut welcomed = false;
+    let mut lines = LineBuffer::new();
+    let mut received = vec![];
+    let mut buffer = [0; 1024];
+    while !welcomed || !pending.is_empty() {
+        let size = connection.read(&mut buffer).await?;
+        if size == 0 {
+            return Err(TrirkError::new(
+                TrirkErrorKind::ConnectionClosed,
+                "connection closed during the handshake",
+            ));
+        }
+        if let Err(err) = lines.extend(&buffer[..size]) {
+            warn!(error = %err, "oversized line during the handshake");
+        }
+        received.extend_from_slice(&buffer[..size]);
+        while let Some(line) = lines.next_line() {
+            // unparsable lines reach the reader along with the others
+            let Ok(message) = parser.parse_bytes(&line) else {
+                continue;
+            };
+            match reply(&message) {
+                Some(Reply::Welcome) => {
+                    debug!("welcomed");
+                    welcomed = true;
+                }
+                Some(Reply::Acknowledged(capabilities)) => {
+                    debug!(?capabilities, "capabilities acknowledged");
+                    for capability in capabilities {
+                        pending.remove(capability);
+                    }
+                }
+                Some(Reply::Rejected(capabilities)) => {
+                    return Err(TrirkError::new(
+                        TrirkErrorKind::CapabilityRejected,
+                        format!("capabilities rejected: {}", capabilities.join(", ")),
+                    ))
+                }
+                Some(Reply::AuthFailed(text)) => {
+                    return Err(TrirkError::new(TrirkErrorKind::Auth, text))
+                }
+                None => {}
+            }
+        }
+    }
+    let mut lines = LineBuffer::new();
+    // already reported while reading them above
+    let _ = lines.extend(&received);
+    Ok((connection, lines))
+}
+
+fn reply(message: &TwitchMessage) -> Option<Reply<'_>> {
+    let trailing = message.parameters().as_deref().unwrap_or_default();
+    match message.command().command() {
+        CommandType::Numeric(1) => Some(Reply::Welcome),
+        CommandType::Cap(subcommand) => {
+            let capabilities = trailing
+                .split_whitespace()
+                .map(|capability| {
+                    capability
+                        .strip_prefix(CAPABILITY_PREFIX)
+                        .unwrap_or(capability)
+                })
+                .collect();
+            match subcommand.as_str() {
+                "ACK" => Some(Reply::Acknowledged(capabilities)),
+                "NAK" => Some(Reply::Rejected(capabilities)),
+                _ => None,
+            }
+        }
+        CommandType::Notice if AUTH_FAILURES.contains(&trailing) => {
+            Some(Reply::AuthFailed(trailing))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parser::{trirk_parser::TrirkParser, TwitchMessage};
+
+    use super::{reply, Reply};
+
+    fn parse(line: &str) -> TwitchMessage {
+        TrirkParser::new().parse(line).unwrap()
+    }
+
+    #[test]
+    fn should_recognize_welcome() {
+        assert_eq!(
+            Some(Reply::Welcome),
+            reply(&parse(":tmi.twitch.tv 001 trirk :Welcome, GLHF!"))
+        );
+    }
+
+    #[test]
+    fn should_recognize_capability_replies() {
+        assert_eq!(
+            Some(Reply::Acknowledged(vec!["commands", "tags"])),
+            reply(&parse(
+                ":tmi.twitch.tv CAP * ACK :twitch.tv/commands twitch.tv/tags"
+            ))
+        );
+        assert_eq!(
+            Some(Reply::Rejected(vec!["bogus"])),
+            reply(&parse(":tmi.twitch.tv CAP * NAK :twitch.tv/bogus"))
+        );
+    }
+
+    #[test]
+    fn should_recognize_auth_failures() {
+        assert_eq!(
+            Some(Reply::AuthFailed("Login authentication failed")),
+            reply(&parse(
+                ":tmi.twitch.tv NOTICE * :Login authentication failed"
+            ))
+        );
+        assert_eq!(
+            Some(Reply::AuthFailed("Improperly formatted auth")),
+            reply(&parse(":tmi.twitch.tv NOTICE * :Improperly formatted auth"))
+        );
+        assert_eq!(
+            None,
+            reply(&parse(
+                "@msg-id=slow_on :tmi.twitch.tv NOTICE #dallas :This room is now in slow mode."
+            ))
+        );
+    }
+}
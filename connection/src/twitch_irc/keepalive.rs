@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Trailing parameter of our PINGs, echoed back by Twitch in the PONG.
+pub(super) const KEEPALIVE_TOKEN: &str = "trirk-keepalive";
+
+/// What to do once [`Keepalive::deadline`] passes.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Expired {
+    SendPing,
+    /// Our PING went unanswered, the connection is most likely half-open.
+    Dead,
+}
+
+/// Client side keepalive: PINGs the server every `interval`, whatever the
+/// traffic, to measure the latency, and declares the connection dead when
+/// the PONG does not arrive within `timeout`, even if other lines do.
+pub(super) struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    next_ping: Instant,
+    ping_sent: Option<Instant>,
+    latency: Option<Duration>,
+}
+
+impl Keepalive {
+    pub(super) fn new(interval: Duration, timeout: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            interval,
+            timeout,
+            next_ping: now + interval,
+            ping_sent: None,
+            latency: None,
+        }
+    }
+
+    /// When reading should stop waiting for the server.
+    pub(super) fn deadline(&self) -> Instant {
+        match self.ping_sent {
+            Some(sent) => sent + self.timeout,
+            None => self.next_ping,
+        }
+    }
+
+    /// Round trip of the last answered PING, kept while a PING is
+    /// outstanding.
+    pub(super) fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// What to do now that [`Keepalive::deadline`] passed.
+    pub(super) fn expired(&mut self) -> Expired {
+        let now = Instant::now();
+        if self.ping_sent.is_some() {
+            return Expired::Dead;
+        }
+        self.ping_sent = Some(now);
+        self.next_ping = now + self.interval;
+        Expired::SendPing
+    }
+
+    /// Records the PONG to our PING, `false` when none was outstanding.
+    pub(super) fn pong(&mut self) -> bool {
+        let Some(sent) = self.ping_sent.take() else {
+            return false;
+        };
+        self.latency = Some(Instant::now() - sent);
+        true
+    }
+
+    /// Starts over on a fresh connection.
+    pub(super) fn reset(&mut self) {
+        let now = Instant::now();
+        self.ping_sent = None;
+        self.next_ping = now + self.interval;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::time::{advance, Instant};
+
+    use super::{Expired, Keepalive};
+
+    #[tokio::test(start_paused = true)]
+    async fn should_ping_every_interval_and_measure_latency() {
+        let start = Instant::now();
+        let mut keepalive = Keepalive::new(Duration::from_secs(60), Duration::from_secs(10));
+        assert_eq!(start + Duration::from_secs(60), keepalive.deadline());
+        advance(Duration::from_secs(60)).await;
+        assert_eq!(Expired::SendPing, keepalive.expired());
+        advance(Duration::from_millis(150)).await;
+        assert!(keepalive.pong());
+        assert_eq!(Some(Duration::from_millis(150)), keepalive.latency());
+        assert!(!keepalive.pong());
+        assert_eq!(start + Duration::from_secs(120), keepalive.deadline());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_declare_dead_after_silence() {
+        let mut keepalive = Keepalive::new(Duration::from_secs(60), Duration::from_secs(10));
+        advance(Duration::from_secs(60)).await;
+        assert_eq!(Expired::SendPing, keepalive.expired());
+        assert_eq!(
+            Instant::now() + Duration::from_secs(10),
+            keepalive.deadline()
+        );
+        advance(Duration::from_secs(10)).await;
+        assert_eq!(Expired::Dead, keepalive.expired());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_declare_dead_without_pong_and_keep_the_latency() {
+        let mut keepalive = Keepalive::new(Duration::from_secs(60), Duration::from_secs(10));
+        advance(Duration::from_secs(60)).await;
+        assert_eq!(Expired::SendPing, keepalive.expired());
+        advance(Duration::from_millis(150)).await;
+        assert!(keepalive.pong());
+        advance(Duration::from_secs(60)).await;
+        assert_eq!(Expired::SendPing, keepalive.expired());
+        assert_eq!(
+            Instant::now() + Duration::from_secs(10),
+            keepalive.deadline()
+        );
+        advance(Duration::from_secs(10)).await;
+        assert_eq!(Expired::Dead, keepalive.expired());
+        assert_eq!(Some(Duration::from_millis(150)), keepalive.latency());
+    }
+}
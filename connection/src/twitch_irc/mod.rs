@@ -1,117 +1,762 @@
 use std::{
-    io::{Result, Write, Read},
     marker::PhantomData,
-    ops::{Deref, DerefMut}, net::TcpStream,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
 };
 
-use parser::{trirk_parser::TrirkParser, TwitchMessage};
+use futures::{channel::mpsc, Sink, SinkExt, Stream, StreamExt};
+use parser::{trirk_parser::TrirkParser, ChannelName, TwitchMessage};
+use tokio::sync::{broadcast, watch};
+use tracing::{info, Instrument};
 
-use crate::error::TrirkError;
+use crate::error::{TrirkError, TrirkErrorKind};
 
-use self::config::TwitchConfig;
+use self::{
+    capture::Recorder,
+    config::TwitchConfig,
+    driver::{Driver, Status},
+    handshake::establish,
+    outgoing::Outgoing,
+    rate_limit::{RateLimiter, SharedLimiter},
+    reconnect::{notify, ConnectionEvent},
+    split::{Reader, Sender},
+};
 
+pub mod capture;
 pub mod config;
+mod driver;
+mod handshake;
+mod keepalive;
+pub mod outgoing;
+pub mod pool;
+pub mod rate_limit;
+pub mod reconnect;
+pub mod split;
+pub mod transport;
 
-const IRC_HOST: &str = "irc.chat.twitch.tv";
-const IRC_PORT: usize = 6667;
-const CAP_REQ: &str = "CAP REQ :twitch.tv/";
+const LIFECYCLE_CAPACITY: usize = 16;
+/// Requests queued for the driver before senders have to wait.
+pub(super) const REQUEST_CAPACITY: usize = 64;
 
 #[derive(Clone)]
 pub struct ClosedConnection;
-pub struct OpenedConnection(TcpStream);
+/// Login with the configured nickname and oauth token.
+#[derive(Clone)]
+pub struct Authenticated;
+/// Read-only `justinfan` login, no credentials needed but nothing can be
+/// sent to chat.
+#[derive(Clone)]
+pub struct Anonymous;
+
+/// Handle to a connection driven by a background task, which owns the
+/// socket, answers keepalives, reconnects and applies the rate limits.
+pub struct OpenedConnection {
+    reader: Reader,
+    /// Always a `Sender<Authenticated>`: the [`TwitchIrc`] holding it only
+    /// exposes what its login `L` allows, and [`TwitchIrc::split`] hands
+    /// it out as a `Sender<L>`.
+    sender: Sender,
+}
 
-impl Deref for OpenedConnection {
-    type Target = TcpStream;
+#[derive(Clone)]
+pub struct TwitchIrc<T = ClosedConnection, L = Authenticated> {
+    configuration: TwitchConfig,
+    parser: TrirkParser,
+    lifecycle: broadcast::Sender<ConnectionEvent>,
+    limiter: SharedLimiter,
+    connection: T,
+    _marker: PhantomData<(T, L)>,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl<T, L> TwitchIrc<T, L> {
+    /// Subscribes to connection lifecycle events. Subscribe before
+    /// [`TwitchIrc::open_connection`] to also see the first `Connected`.
+    pub fn lifecycle(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.lifecycle.subscribe()
     }
 }
 
-impl DerefMut for OpenedConnection {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+impl TwitchIrc<ClosedConnection> {
+    /// Fails with [`TrirkErrorKind::Config`](crate::error::TrirkErrorKind::Config)
+    /// when `config` has no oauth token, see [`TwitchIrc::anonymous`] to
+    /// read without one.
+    pub fn new(config: TwitchConfig) -> Result<Self, TrirkError> {
+        if config.oauth.is_empty() {
+            return Err(TrirkError::new(
+                TrirkErrorKind::Config,
+                "no oauth token, use TwitchIrc::anonymous to log in without one",
+            ));
+        }
+        Ok(Self::with_configuration(config))
     }
 }
 
-#[derive(Clone)]
-pub struct TwitchIrc<T = ClosedConnection> {
-    configuration: TwitchConfig,
-    connection: T,
-    _marker: PhantomData<T>,
+impl TwitchIrc<ClosedConnection, Anonymous> {
+    /// Read-only handle: it can read and join channels but has no
+    /// [`TwitchIrc::privmsg`]. Logs in as `justinfan<random>` whatever
+    /// the nickname and oauth of `config`, which
+    /// [`TwitchConfig::anonymous`] builds without credentials.
+    pub fn anonymous(config: TwitchConfig) -> Self {
+        Self::with_configuration(config.into_anonymous())
+    }
 }
 
-impl TwitchIrc<ClosedConnection> {
-    #[inline(always)]
-    pub fn new(config: TwitchConfig) -> Self {
+impl<L> TwitchIrc<ClosedConnection, L> {
+    fn with_configuration(configuration: TwitchConfig) -> Self {
         Self {
-            configuration: config,
+            configuration,
+            parser: TrirkParser::new(),
+            lifecycle: broadcast::channel(LIFECYCLE_CAPACITY).0,
+            limiter: RateLimiter::shared(),
             connection: ClosedConnection,
             _marker: PhantomData,
         }
     }
 
-    pub async fn open_connection(self) -> Result<TwitchIrc<OpenedConnection>> {
-        println!(
-            "opening connection for channel '{}', with nickname '{}'",
-            self.configuration.channel, self.configuration.nickname
-        );
-        let mut connection = TcpStream::connect(format!("{}:{}", IRC_HOST, IRC_PORT))?;
-
-        connection
-            .write_all(
-                format!(
-                    "PASS {pass}\r\nNICK {user}\r\nJOIN #{join}\r\n{CAP_REQ}{command}\r\n{CAP_REQ}{membership}\r\n{CAP_REQ}{tags}\r\n",
-                    pass = self.configuration.oauth,
-                    user = self.configuration.nickname,
-                    join = self.configuration.channel,
-                    command = "commands",
-                    membership = "membership",
-                    tags = "tags"
-                )
-                .as_bytes(),
-            )?;
-        connection.flush()?;
-        let irc = TwitchIrc::<OpenedConnection> {
+    /// Replaces the default parser, e.g. with one that has custom tag decoders.
+    pub fn with_parser(mut self, parser: TrirkParser) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    /// Shares the rate limits with other connections of the same account.
+    pub(super) fn with_limiter(mut self, limiter: SharedLimiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Connects and waits for Twitch to accept the login. Fails with
+    /// [`TrirkErrorKind::Auth`](crate::error::TrirkErrorKind::Auth) for a
+    /// bad token and
+    /// [`TrirkErrorKind::CapabilityRejected`](crate::error::TrirkErrorKind::CapabilityRejected)
+    /// when a requested capability is refused.
+    ///
+    /// Everything the connection logs, now and from its background task,
+    /// is inside a `connection` span with the host, port and nickname.
+    #[tracing::instrument(
+        name = "connection",
+        skip_all,
+        fields(
+            host = %self.configuration.host,
+            port = self.configuration.port(),
+            nickname = %self.configuration.nickname,
+        )
+    )]
+    pub async fn open_connection(self) -> Result<TwitchIrc<OpenedConnection, L>, TrirkError> {
+        let recorder = match &self.configuration.capture {
+            Some(path) => Some(Recorder::create(path).await?),
+            None => None,
+        };
+        let established = establish(&self.configuration, &self.parser, recorder.as_ref()).await?;
+        let (incoming_sender, incoming) = mpsc::unbounded();
+        let (requests, requests_receiver) = mpsc::channel(REQUEST_CAPACITY);
+        let (status_sender, status) = watch::channel(Status::default());
+        let driver = Driver::new(
+            self.configuration.clone(),
+            self.parser.clone(),
+            self.lifecycle.clone(),
+            established,
+            incoming_sender,
+            status_sender,
+            recorder,
+        )
+        .with_limiter(self.limiter.clone());
+        info!("connected");
+        notify(&self.lifecycle, ConnectionEvent::Connected);
+        tokio::spawn(driver.run(requests_receiver).in_current_span());
+        Ok(TwitchIrc {
             configuration: self.configuration,
-            connection: OpenedConnection(connection),
+            parser: self.parser,
+            lifecycle: self.lifecycle,
+            limiter: self.limiter,
+            connection: OpenedConnection {
+                reader: Reader::new(incoming, requests.clone()),
+                sender: Sender::new(requests, status),
+            },
             _marker: PhantomData,
+        })
+    }
+}
+
+impl TwitchIrc<OpenedConnection> {
+    /// Queues raw bytes, written as is without rate limiting.
+    pub async fn send_bytes(&mut self, message: &[u8]) -> Result<(), TrirkError> {
+        self.connection.sender.send_bytes(message).await
+    }
+
+    /// Sends `message` to the first channel from the configuration, fails
+    /// with [`TrirkErrorKind::Config`] when there is none.
+    pub async fn privmsg(&mut self, message: &str) -> Result<(), TrirkError> {
+        let Some(channel) = self.configuration.channels.first().cloned() else {
+            return Err(TrirkError::new(
+                TrirkErrorKind::Config,
+                "no channel configured, use privmsg_to",
+            ));
         };
-        Ok(irc)
+        self.privmsg_to(&channel, message).await
+    }
+
+    /// See [`Sender::privmsg_to`].
+    pub async fn privmsg_to(
+        &mut self,
+        channel: &ChannelName,
+        message: &str,
+    ) -> Result<(), TrirkError> {
+        self.connection.sender.privmsg_to(channel, message).await
     }
 }
 
-const PARSER: TrirkParser = TrirkParser::new();
+impl<L> TwitchIrc<OpenedConnection, L> {
+    /// Splits the connection so that sending does not need the `&mut`
+    /// borrow held by whoever is reading. The [`Sender`] can be cloned
+    /// into as many tasks as needed; the connection stays open until the
+    /// reader and every sender are dropped.
+    pub fn split(self) -> (Reader, Sender<L>) {
+        (self.connection.reader, self.connection.sender.retype())
+    }
 
-impl TwitchIrc<OpenedConnection> {
-    pub fn send_bytes(&mut self, message: &[u8]) -> Result<()> {
-        self.connection.write_all(message)?;
-        Ok(())
+    /// See [`Sender::join`].
+    pub async fn join(&mut self, channel: ChannelName) -> Result<(), TrirkError> {
+        self.connection.sender.join(channel).await
     }
 
-    pub fn privmsg(&mut self, message: &str) -> Result<()> {
-        self.send_bytes(
-            format!("PRIVMSG #{} :{}\r\n", self.configuration.channel, message).as_bytes(),
-        )
+    pub async fn part(&mut self, channel: &ChannelName) -> Result<(), TrirkError> {
+        self.connection.sender.part(channel).await
     }
 
-    pub fn read_next(&mut self) -> std::result::Result<TwitchMessage, TrirkError> {
-        let mut buffer = vec![0; 1024];
-        match self.connection.read(&mut buffer) {
-            Ok(size) => {
-                buffer.truncate(size);
-                let message = String::from_utf8(buffer)?;
-                let twitch_message = PARSER.parse(message)?;
-                Ok(twitch_message)
-            }
-            Err(e) => Err(e)?,
-        }
+    /// Channels Twitch confirmed joining, rejoined automatically after a
+    /// reconnect.
+    pub fn channels(&self) -> Vec<ChannelName> {
+        self.connection.sender.channels()
+    }
+
+    /// Round trip of the last keepalive PING, `None` until one was answered.
+    pub fn latency(&self) -> Option<Duration> {
+        self.connection.sender.latency()
+    }
+
+    /// See [`Reader::read_next`].
+    pub async fn read_next(&mut self) -> Result<TwitchMessage, TrirkError> {
+        self.connection.reader.read_next().await
+    }
+}
+
+/// Incoming messages, ending once the connection is closed for good.
+impl<L: Unpin> Stream for TwitchIrc<OpenedConnection, L> {
+    type Item = Result<TwitchMessage, TrirkError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.connection.reader.poll_next_unpin(cx)
+    }
+}
+
+impl Sink<Outgoing> for TwitchIrc<OpenedConnection> {
+    type Error = TrirkError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.connection.sender.poll_ready_unpin(cx)
     }
 
-    pub fn pong(&mut self) -> Result<()> {
-        self.send_bytes(b"PONG\r\n")
+    fn start_send(mut self: Pin<&mut Self>, item: Outgoing) -> Result<(), Self::Error> {
+        self.connection.sender.start_send_unpin(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.connection.sender.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.connection.sender.poll_close_unpin(cx)
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::{error::Error, time::Duration};
+
+    use futures::{SinkExt, StreamExt};
+    use mock_server::{messages, MockServer, Script};
+    use parser::{
+        trirk_parser::{error::UnparsableErrorKind, line_buffer::MAX_LINE_LENGTH},
+        ChannelName, CommandType, Event, NoticeKind, TwitchMessage, UserNoticeKind,
+    };
+
+    use crate::error::{TrirkError, TrirkErrorKind};
+
+    use super::{
+        config::{TwitchConfig, TwitchConfigBuilder},
+        outgoing::Outgoing,
+        reconnect::{ConnectionEvent, ReconnectPolicy},
+        split::Reader,
+        TwitchIrc,
+    };
+
+    pub(super) fn local_builder(server: &MockServer) -> TwitchConfigBuilder {
+        let mut builder = TwitchConfig::builder();
+        builder
+            .nickname("trirk")
+            .oauth("oauth:token")
+            .host(server.host())
+            .port(server.port())
+            .tls(false)
+            .channel(ChannelName::new("dallas").unwrap())
+            .reconnect(ReconnectPolicy {
+                initial_delay: Duration::from_millis(10),
+                max_attempts: Some(3),
+                ..Default::default()
+            });
+        builder
+    }
+
+    pub(super) fn local_config(server: &MockServer) -> TwitchConfig {
+        local_builder(server).build().unwrap()
+    }
+
+    async fn read_until(reader: &mut Reader, command: CommandType) -> TwitchMessage {
+        loop {
+            let message = reader.read_next().await.unwrap();
+            if message.command().command() == &command {
+                return message;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_login_anonymously_whatever_the_credentials() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let _irc = TwitchIrc::anonymous(local_config(&server))
+            .open_connection()
+            .await
+            .unwrap();
+        server.wait_for("NICK justinfan").await;
+        assert!(!server
+            .received()
+            .iter()
+            .any(|line| line.starts_with("PASS")));
+    }
+
+    #[test]
+    fn should_refuse_authenticated_handles_without_oauth() {
+        let config = TwitchConfig::anonymous()
+            .channel(ChannelName::new("dallas").unwrap())
+            .build()
+            .unwrap();
+        let Err(err) = TwitchIrc::new(config) else {
+            panic!("an empty oauth must be refused");
+        };
+        assert_eq!(&TrirkErrorKind::Config, err.kind());
+    }
+
+    #[tokio::test]
+    async fn should_stream_messages_and_sink_commands() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let mut irc = TwitchIrc::new(local_config(&server))
+            .unwrap()
+            .open_connection()
+            .await
+            .unwrap();
+        let room_state = loop {
+            let message = irc.next().await.unwrap().unwrap();
+            if message.command().command() == &CommandType::RoomState {
+                break message;
+            }
+        };
+        assert_eq!(
+            &Some(ChannelName::new("dallas").unwrap()),
+            room_state.command().channel()
+        );
+        irc.send(Outgoing::Privmsg {
+            channel: ChannelName::new("dallas").unwrap(),
+            text: "hi".into(),
+        })
+        .await
+        .unwrap();
+        server.wait_for("PRIVMSG").await;
+        assert_eq!(
+            vec![
+                "PASS oauth:token",
+                "NICK trirk",
+                "CAP REQ :twitch.tv/commands",
+                "CAP REQ :twitch.tv/membership",
+                "CAP REQ :twitch.tv/tags",
+                "JOIN #dallas",
+                "PRIVMSG #dallas :hi",
+            ],
+            server.received()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_send_while_another_task_reads() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let irc = TwitchIrc::new(local_config(&server))
+            .unwrap()
+            .open_connection()
+            .await
+            .unwrap();
+        let (mut reader, sender) = irc.split();
+        let reading = tokio::spawn(async move {
+            loop {
+                let message = reader.read_next().await?;
+                if message.command().command() == &CommandType::PrivMSG {
+                    return Ok::<_, TrirkError>(message);
+                }
+            }
+        });
+        let dallas = ChannelName::new("dallas").unwrap();
+        sender.clone().privmsg_to(&dallas, "hi").await.unwrap();
+        // the mock answers the JOIN before sending the line below
+        server.wait_for("JOIN #dallas").await;
+        server.wait_for("PRIVMSG #dallas :hi").await;
+        server.send(&messages::privmsg("dallas", "ronni", "hello"));
+        let message = reading.await.unwrap().unwrap();
+        assert_eq!(&Some("hello".to_string()), message.parameters());
+        assert_eq!(vec![dallas], sender.channels());
+    }
+
+    #[tokio::test]
+    async fn should_connect_over_tls_to_a_trusted_ca() {
+        let server = MockServer::start_tls(Script::new()).await.unwrap();
+        let config = local_builder(&server)
+            .tls(true)
+            .ca_certificate(server.certificate().unwrap().as_bytes().to_vec())
+            .build()
+            .unwrap();
+        let _irc = TwitchIrc::new(config)
+            .unwrap()
+            .open_connection()
+            .await
+            .unwrap();
+        server.wait_for("JOIN #dallas").await;
+    }
+
+    #[tokio::test]
+    async fn should_refuse_tls_servers_signed_by_an_untrusted_ca() {
+        let server = MockServer::start_tls(Script::new()).await.unwrap();
+        let impostor = MockServer::start_tls(Script::new()).await.unwrap();
+        let config = local_builder(&server)
+            .tls(true)
+            .ca_certificate(impostor.certificate().unwrap().as_bytes().to_vec())
+            .build()
+            .unwrap();
+        let Err(err) = TwitchIrc::new(config).unwrap().open_connection().await else {
+            panic!("the certificate should not be trusted");
+        };
+        assert!(matches!(err.kind(), TrirkErrorKind::Io));
+        assert!(server.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_fail_on_rejected_login() {
+        let server = MockServer::start(Script::new().with_rejected_login())
+            .await
+            .unwrap();
+        let Err(err) = TwitchIrc::new(local_config(&server))
+            .unwrap()
+            .open_connection()
+            .await
+        else {
+            panic!("login should fail");
+        };
+        assert!(matches!(err.kind(), TrirkErrorKind::Auth));
+    }
+
+    #[tokio::test]
+    async fn should_fail_on_rejected_capability() {
+        let server = MockServer::start(Script::new().with_rejected_capability("tags"))
+            .await
+            .unwrap();
+        let Err(err) = TwitchIrc::new(local_config(&server))
+            .unwrap()
+            .open_connection()
+            .await
+        else {
+            panic!("capability should be rejected");
+        };
+        assert!(matches!(err.kind(), TrirkErrorKind::CapabilityRejected));
+    }
+
+    #[tokio::test]
+    async fn should_reject_joining_suspended_channels() {
+        let server = MockServer::start(Script::new().with_suspended_channel("banned"))
+            .await
+            .unwrap();
+        let mut irc = TwitchIrc::new(local_config(&server))
+            .unwrap()
+            .open_connection()
+            .await
+            .unwrap();
+        let err = irc
+            .join(ChannelName::new("banned").unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(err.kind(), TrirkErrorKind::JoinRejected));
+        assert_eq!(vec![ChannelName::new("dallas").unwrap()], irc.channels());
+    }
+
+    #[tokio::test]
+    async fn should_only_count_confirmed_configured_channels_as_joined() {
+        let server = MockServer::start(Script::new().with_suspended_channel("banned"))
+            .await
+            .unwrap();
+        let config = local_builder(&server)
+            .channel(ChannelName::new("banned").unwrap())
+            .build()
+            .unwrap();
+        let irc = TwitchIrc::new(config).unwrap();
+        let (mut reader, sender) = irc.open_connection().await.unwrap().split();
+        read_until(&mut reader, CommandType::Notice).await;
+        assert_eq!(vec![ChannelName::new("dallas").unwrap()], sender.channels());
+    }
+
+    #[tokio::test]
+    async fn should_not_count_channels_parted_while_joining_as_joined() {
+        let server = MockServer::start(Script::new().with_unanswered_join("quiet"))
+            .await
+            .unwrap();
+        let irc = TwitchIrc::new(local_config(&server)).unwrap();
+        let (mut reader, mut sender) = irc.open_connection().await.unwrap().split();
+        read_until(&mut reader, CommandType::RoomState).await;
+        let quiet = ChannelName::new("quiet").unwrap();
+        sender.send(Outgoing::Join(quiet.clone())).await.unwrap();
+        server.wait_for("JOIN #quiet").await;
+        sender.part(&quiet).await.unwrap();
+        server.wait_for("PART #quiet").await;
+        // Twitch confirming the join after all
+        server.send(&messages::roomstate("quiet"));
+        read_until(&mut reader, CommandType::RoomState).await;
+        assert_eq!(vec![ChannelName::new("dallas").unwrap()], sender.channels());
+    }
+
+    #[tokio::test]
+    async fn should_rejoin_when_asked_to_reconnect() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let irc = TwitchIrc::new(local_config(&server)).unwrap();
+        let mut lifecycle = irc.lifecycle();
+        let (mut reader, _sender) = irc.open_connection().await.unwrap().split();
+        read_until(&mut reader, CommandType::RoomState).await;
+        server.reconnect();
+        read_until(&mut reader, CommandType::Reconnect).await;
+        read_until(&mut reader, CommandType::RoomState).await;
+        let joins = server
+            .received()
+            .into_iter()
+            .filter(|line| line == "JOIN #dallas")
+            .count();
+        assert_eq!((2, 2), (server.connections(), joins));
+        assert_eq!(ConnectionEvent::Connected, lifecycle.recv().await.unwrap());
+        assert!(matches!(
+            lifecycle.recv().await.unwrap(),
+            ConnectionEvent::Disconnected { reason } if reason == "server requested a reconnect"
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_reconnect_after_a_dropped_connection() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let irc = TwitchIrc::new(local_config(&server)).unwrap();
+        let mut lifecycle = irc.lifecycle();
+        let (mut reader, mut sender) = irc.open_connection().await.unwrap().split();
+        read_until(&mut reader, CommandType::RoomState).await;
+        server.disconnect();
+        read_until(&mut reader, CommandType::RoomState).await;
+        let events = [
+            lifecycle.recv().await.unwrap(),
+            lifecycle.recv().await.unwrap(),
+            lifecycle.recv().await.unwrap(),
+            lifecycle.recv().await.unwrap(),
+        ];
+        assert!(matches!(
+            events,
+            [
+                ConnectionEvent::Connected,
+                ConnectionEvent::Disconnected { .. },
+                ConnectionEvent::Reconnecting { attempt: 1, .. },
+                ConnectionEvent::Connected,
+            ]
+        ));
+        let dallas = ChannelName::new("dallas").unwrap();
+        sender.privmsg_to(&dallas, "still here").await.unwrap();
+        server.wait_for("PRIVMSG #dallas :still here").await;
+    }
+
+    #[tokio::test]
+    async fn should_close_once_reconnecting_gives_up() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let irc = TwitchIrc::new(local_config(&server)).unwrap();
+        let mut lifecycle = irc.lifecycle();
+        let (mut reader, _sender) = irc.open_connection().await.unwrap().split();
+        read_until(&mut reader, CommandType::RoomState).await;
+        drop(server);
+        let err = loop {
+            if let Err(err) = reader.read_next().await {
+                break err;
+            }
+        };
+        assert!(matches!(err.kind(), TrirkErrorKind::ConnectionClosed));
+        let cause = err.source().unwrap().downcast_ref::<TrirkError>().unwrap();
+        assert!(!matches!(cause.kind(), TrirkErrorKind::ConnectionClosed));
+        assert!(reader.next().await.is_none());
+        let mut last_errors = vec![];
+        while let Ok(event) = lifecycle.try_recv() {
+            if let ConnectionEvent::Reconnecting { last_error, .. } = event {
+                last_errors.push(last_error);
+            }
+        }
+        assert!(matches!(
+            last_errors.as_slice(),
+            [None, Some(failed), Some(_)] if failed == &cause.to_string()
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_stop_reconnecting_when_capabilities_are_rejected() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let irc = TwitchIrc::new(local_config(&server)).unwrap();
+        let (mut reader, _sender) = irc.open_connection().await.unwrap().split();
+        read_until(&mut reader, CommandType::RoomState).await;
+        server.set_script(Script::new().with_rejected_capability("tags"));
+        server.disconnect();
+        let err = loop {
+            if let Err(err) = reader.read_next().await {
+                break err;
+            }
+        };
+        assert!(matches!(err.kind(), TrirkErrorKind::CapabilityRejected));
+        assert!(reader.next().await.is_none());
+        assert_eq!(2, server.connections());
+    }
+
+    #[tokio::test]
+    async fn should_report_rate_limited_messages() {
+        let server =
+            MockServer::start(Script::new().with_message_limit(1, Duration::from_secs(30)))
+                .await
+                .unwrap();
+        let irc = TwitchIrc::new(local_config(&server)).unwrap();
+        let (mut reader, mut sender) = irc.open_connection().await.unwrap().split();
+        let dallas = ChannelName::new("dallas").unwrap();
+        sender.privmsg_to(&dallas, "one").await.unwrap();
+        sender.privmsg_to(&dallas, "two").await.unwrap();
+        let err = loop {
+            if let Err(err) = reader.read_next().await {
+                break err;
+            }
+        };
+        assert!(matches!(err.kind(), TrirkErrorKind::RateLimited));
+        assert!(err.to_string().contains("#dallas"), "{err}");
+        let notice = reader.read_next().await.unwrap();
+        assert!(matches!(
+            notice.into_event(),
+            Event::Notice { channel: Some(channel), kind: NoticeKind::RateLimited, .. }
+                if channel == dallas
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_classify_user_notices_and_timeouts() {
+        let subscription = messages::subscription("dallas", "ronni", 3);
+        let server = MockServer::start(Script::new().with_welcome_line(&subscription))
+            .await
+            .unwrap();
+        let irc = TwitchIrc::new(local_config(&server)).unwrap();
+        let mut reader = irc.open_connection().await.unwrap().split().0;
+        let dallas = ChannelName::new("dallas").unwrap();
+        let resub = read_until(&mut reader, CommandType::UserNotice).await;
+        assert!(matches!(
+            resub.into_event(),
+            Event::UserNotice { channel, kind: UserNoticeKind::Resub, tags: Some(tags), .. }
+                if channel == dallas && tags.login().as_str() == "ronni"
+        ));
+        server.send(&messages::timeout("dallas", "ronni", 600));
+        let timeout = read_until(&mut reader, CommandType::ClearChat).await;
+        assert!(matches!(
+            timeout.into_event(),
+            Event::Timeout { channel, user, duration, .. }
+                if channel == dallas && user == "ronni" && duration == Duration::from_secs(600)
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_answer_server_pings() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let irc = TwitchIrc::new(local_config(&server)).unwrap();
+        let mut reader = irc.open_connection().await.unwrap().split().0;
+        server.send("PING :tmi.twitch.tv");
+        let ping = read_until(&mut reader, CommandType::Ping).await;
+        assert_eq!(&Some("tmi.twitch.tv".to_string()), ping.parameters());
+        assert_eq!("PONG :tmi.twitch.tv", server.wait_for("PONG").await);
+    }
+
+    #[tokio::test]
+    async fn should_measure_latency_on_a_chatty_connection() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let config = local_builder(&server)
+            .ping_interval(Duration::from_millis(100))
+            .pong_timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let irc = TwitchIrc::new(config).unwrap();
+        let (mut reader, sender) = irc.open_connection().await.unwrap().split();
+        // PINGs go out however chatty the connection is
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sender.latency().is_none() {
+                server.send(&messages::privmsg("dallas", "ronni", "hi"));
+                read_until(&mut reader, CommandType::PrivMSG).await;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        server.wait_for("PING :trirk-keepalive").await;
+    }
+
+    #[tokio::test]
+    async fn should_report_oversized_lines_and_keep_reading() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let irc = TwitchIrc::new(local_config(&server)).unwrap();
+        let mut reader = irc.open_connection().await.unwrap().split().0;
+        server.send(&"a".repeat(MAX_LINE_LENGTH));
+        server.send(&messages::privmsg("dallas", "ronni", "hi"));
+        let err = loop {
+            if let Err(err) = reader.read_next().await {
+                break err;
+            }
+        };
+        assert_eq!(
+            Some(UnparsableErrorKind::LineTooLong),
+            err.parse_error().map(|err| err.kind())
+        );
+        read_until(&mut reader, CommandType::PrivMSG).await;
+    }
+
+    #[tokio::test]
+    async fn should_record_raw_traffic() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let path = std::env::temp_dir().join(format!("trirk-{}.capture", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut config = local_config(&server);
+        config.capture = Some(path.clone());
+        let _irc = TwitchIrc::new(config)
+            .unwrap()
+            .open_connection()
+            .await
+            .unwrap();
+        let capture = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let capture = tokio::fs::read_to_string(&path).await.unwrap();
+                if capture.contains("> JOIN #dallas") {
+                    return capture;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(capture.contains(" > PASS ***\n"), "{capture}");
+        assert!(!capture.contains("oauth:token"));
+        assert!(capture.contains(" < :tmi.twitch.tv 001 trirk :Welcome, GLHF!\n"));
+    }
+}
@@ -0,0 +1,135 @@
+use parser::ChannelName;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Longest PRIVMSG text Twitch accepts, in characters.
+pub const MAX_MESSAGE_LENGTH: usize = 500;
+
+/// Commands accepted by the [`Sink`](futures::Sink) side of
+/// [`TwitchIrc`](super::TwitchIrc).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outgoing {
+    /// Sanitized, split and rate limited like
+    /// [`TwitchIrc::privmsg_to`](super::TwitchIrc::privmsg_to).
+    Privmsg {
+        channel: ChannelName,
+        text: String,
+    },
+    /// Joins without waiting for Twitch to confirm it.
+    Join(ChannelName),
+    Part(ChannelName),
+    /// Written as is, bypassing the rate limiter. Lost when the socket
+    /// breaks while writing them.
+    Raw(Vec<u8>),
+}
+
+/// Makes `text` safe to send as a PRIVMSG: line breaks become spaces, so
+/// they cannot smuggle in another IRC command, and NUL bytes are dropped.
+pub(super) fn sanitize(text: &str) -> String {
+    text.chars()
+        .filter(|&c| c != '\0')
+        .map(|c| if matches!(c, '\r' | '\n') { ' ' } else { c })
+        .collect()
+}
+
+/// Splits `text` into chunks of at most `max` characters, breaking between
+/// words where possible and between graphemes for words longer than `max`.
+/// A single grapheme longer than `max` (e.g. a base letter under hundreds
+/// of combining marks) is broken between its characters as a last resort.
+pub(super) fn split(text: &str, max: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    let mut length = 0;
+    for word in text.split_word_bounds() {
+        let word_length = word.chars().count();
+        if length + word_length > max {
+            push_chunk(&mut chunks, &mut chunk);
+            length = 0;
+        }
+        if word_length <= max {
+            if length > 0 || !word.trim().is_empty() {
+                chunk.push_str(word);
+                length += word_length;
+            }
+            continue;
+        }
+        for grapheme in word.graphemes(true) {
+            let grapheme_length = grapheme.chars().count();
+            if length + grapheme_length > max {
+                push_chunk(&mut chunks, &mut chunk);
+                length = 0;
+            }
+            if grapheme_length <= max {
+                chunk.push_str(grapheme);
+                length += grapheme_length;
+                continue;
+            }
+            for c in grapheme.chars() {
+                if length == max {
+                    push_chunk(&mut chunks, &mut chunk);
+                    length = 0;
+                }
+                chunk.push(c);
+                length += 1;
+            }
+        }
+    }
+    push_chunk(&mut chunks, &mut chunk);
+    chunks
+}
+
+fn push_chunk(chunks: &mut Vec<String>, chunk: &mut String) {
+    let trimmed = chunk.trim();
+    if !trimmed.is_empty() {
+        chunks.push(trimmed.to_owned());
+    }
+    chunk.clear();
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sanitize, split, MAX_MESSAGE_LENGTH};
+
+    #[test]
+    fn should_strip_line_breaks_and_nul() {
+        assert_eq!(
+            "hi  PRIVMSG #other :spam",
+            sanitize("hi\r\nPRIVMSG #other :spam\0")
+        );
+    }
+
+    #[test]
+    fn should_not_split_short_messages() {
+        assert_eq!(
+            vec!["Kappa Keepo"],
+            split("Kappa Keepo", MAX_MESSAGE_LENGTH)
+        );
+        assert!(split("   ", MAX_MESSAGE_LENGTH).is_empty());
+    }
+
+    #[test]
+    fn should_split_on_word_boundaries() {
+        assert_eq!(
+            vec!["lorem ipsum", "dolor sit", "amet"],
+            split("lorem ipsum dolor sit amet", 11)
+        );
+    }
+
+    #[test]
+    fn should_split_long_words_on_graphemes() {
+        let family = "👨‍👩‍👧";
+        let chunks = split(&format!("ab {}", family.repeat(3)), 6);
+        assert_eq!(vec!["ab", family, family, family], chunks);
+        assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 6));
+    }
+
+    #[test]
+    fn should_split_graphemes_longer_than_the_limit() {
+        let zalgo = format!("a{}", "\u{301}".repeat(MAX_MESSAGE_LENGTH + 100));
+        let chunks = split(&zalgo, MAX_MESSAGE_LENGTH);
+        assert_eq!(2, chunks.len());
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.chars().count() <= MAX_MESSAGE_LENGTH));
+        assert_eq!(zalgo, chunks.concat());
+    }
+}
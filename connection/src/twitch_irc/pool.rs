@@ -0,0 +1,715 @@
+use std::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    SinkExt, Stream, StreamExt,
+};
+use parser::{ChannelName, TwitchMessage};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+use tracing::{info_span, warn, Instrument};
+
+use crate::error::{TrirkError, TrirkErrorKind};
+
+use super::{
+    config::TwitchConfig,
+    rate_limit::{RateLimiter, SharedLimiter},
+    reconnect::ConnectionEvent,
+    split::{Reader, Sender},
+    Anonymous, Authenticated, ClosedConnection, OpenedConnection, TwitchIrc, REQUEST_CAPACITY,
+};
+
+/// A connection still reconnecting after this many failed attempts has its
+/// channels moved to the others, whatever its policy allows.
+const REBALANCE_AFTER_FAILED_RECONNECTS: u32 = 3;
+
+/// A message read by one of the pool's connections.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PooledMessage {
+    /// Channel the message belongs to, `None` for e.g. PINGs or whispers.
+    pub channel: Option<ChannelName>,
+    pub message: TwitchMessage,
+}
+
+type Reply<T> = oneshot::Sender<Result<T, TrirkError>>;
+
+enum Request {
+    Join(ChannelName, Reply<()>),
+    Part(ChannelName, Reply<()>),
+    Privmsg(ChannelName, String, Reply<()>),
+    Assignments(oneshot::Sender<Vec<Vec<ChannelName>>>),
+}
+
+/// Spreads channels over as many connections as needed, at most
+/// `channels_per_connection` (see [`TwitchConfig`]) on each, and merges
+/// what they read into one stream.
+///
+/// A connection that fails to reconnect three times in a row, or whose
+/// [`ReconnectPolicy`](super::reconnect::ReconnectPolicy) gives up sooner,
+/// is dropped and its channels are joined again on the others.
+pub struct TwitchPool<L = Authenticated> {
+    requests: mpsc::Sender<Request>,
+    incoming: mpsc::UnboundedReceiver<Result<PooledMessage, TrirkError>>,
+    _marker: PhantomData<L>,
+}
+
+impl TwitchPool {
+    /// Starts the pool and joins the configured channels.
+    pub async fn open(configuration: TwitchConfig) -> Result<Self, TrirkError> {
+        Self::start(configuration, TwitchIrc::new).await
+    }
+
+    /// Sends `message` to `channel` through the connection that joined it.
+    pub async fn privmsg_to(
+        &mut self,
+        channel: &ChannelName,
+        message: &str,
+    ) -> Result<(), TrirkError> {
+        let (reply, result) = oneshot::channel();
+        self.request(Request::Privmsg(channel.clone(), message.into(), reply))
+            .await?;
+        result.await.map_err(|_| TrirkError::closed("pool"))?
+    }
+}
+
+impl TwitchPool<Anonymous> {
+    /// Like [`TwitchPool::open`] with read-only anonymous connections.
+    pub async fn open_anonymous(configuration: TwitchConfig) -> Result<Self, TrirkError> {
+        Self::start(configuration, |configuration| {
+            Ok(TwitchIrc::anonymous(configuration))
+        })
+        .await
+    }
+}
+
+impl<L: Send + 'static> TwitchPool<L> {
+    async fn start(
+        mut configuration: TwitchConfig,
+        connect: fn(TwitchConfig) -> Result<TwitchIrc<ClosedConnection, L>, TrirkError>,
+    ) -> Result<Self, TrirkError> {
+        // refuses unusable credentials before any connection is opened
+        connect(configuration.clone())?;
+        let channels = std::mem::take(&mut configuration.channels);
+        let (requests, requests_receiver) = mpsc::channel(REQUEST_CAPACITY);
+        let (incoming_sender, incoming) = mpsc::unbounded();
+        let (progress, progress_receiver) = mpsc::unbounded();
+        let manager = Manager {
+            configuration,
+            connect,
+            shards: vec![],
+            next_shard: 0,
+            limiter: RateLimiter::shared(),
+            incoming: incoming_sender,
+            progress,
+        };
+        tokio::spawn(manager.run(requests_receiver, progress_receiver));
+        let mut pool = Self {
+            requests,
+            incoming,
+            _marker: PhantomData,
+        };
+        // joined all at once, answered as Twitch confirms them
+        let mut confirmations = vec![];
+        for channel in channels {
+            let (reply, confirmation) = oneshot::channel();
+            pool.request(Request::Join(channel, reply)).await?;
+            confirmations.push(confirmation);
+        }
+        for confirmation in confirmations {
+            confirmation
+                .await
+                .map_err(|_| TrirkError::closed("pool"))??;
+        }
+        Ok(pool)
+    }
+}
+
+impl<L> TwitchPool<L> {
+    async fn request(&mut self, request: Request) -> Result<(), TrirkError> {
+        self.requests
+            .send(request)
+            .await
+            .map_err(|_| TrirkError::closed("pool"))
+    }
+
+    /// Joins `channel` on the least loaded connection, opening a new one
+    /// when all are full.
+    pub async fn join(&mut self, channel: ChannelName) -> Result<(), TrirkError> {
+        let (reply, result) = oneshot::channel();
+        self.request(Request::Join(channel, reply)).await?;
+        result.await.map_err(|_| TrirkError::closed("pool"))?
+    }
+
+    pub async fn part(&mut self, channel: &ChannelName) -> Result<(), TrirkError> {
+        let (reply, result) = oneshot::channel();
+        self.request(Request::Part(channel.clone(), reply)).await?;
+        result.await.map_err(|_| TrirkError::closed("pool"))?
+    }
+
+    /// Channels joined on each open connection.
+    pub async fn assignments(&mut self) -> Result<Vec<Vec<ChannelName>>, TrirkError> {
+        let (reply, result) = oneshot::channel();
+        self.request(Request::Assignments(reply)).await?;
+        result.await.map_err(|_| TrirkError::closed("pool"))
+    }
+
+    /// Reads the next message from any of the connections.
+    pub async fn read_next(&mut self) -> Result<PooledMessage, TrirkError> {
+        self.incoming
+            .next()
+            .await
+            .unwrap_or_else(|| Err(TrirkError::closed("pool")))
+    }
+}
+
+/// Messages of every connection, ending once the pool is closed.
+impl<L: Unpin> Stream for TwitchPool<L> {
+    type Item = Result<PooledMessage, TrirkError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming.poll_next_unpin(cx)
+    }
+}
+
+struct Shard {
+    id: usize,
+    /// `None` while the connection opens. Retyped to
+    /// `Sender<Authenticated>` whatever the pool's login, so the manager
+    /// can serve every pool; only [`TwitchPool<Authenticated>`] offers
+    /// privmsg.
+    sender: Option<Sender>,
+    /// Feeds [`deliver`], `None` while the connection opens.
+    outbox: Option<mpsc::UnboundedSender<Outbound>>,
+    channels: Vec<ChannelName>,
+    /// Joins not confirmed yet, with whoever waits for them.
+    joining: Vec<(ChannelName, Vec<Reply<()>>)>,
+    /// Opening the connection, joining, sending and forwarding its
+    /// messages.
+    tasks: Vec<JoinHandle<()>>,
+}
+
+/// What a shard sends besides joins, in order.
+enum Outbound {
+    Privmsg(ChannelName, String, Reply<()>),
+    Part(ChannelName, Option<Reply<()>>),
+}
+
+impl Shard {
+    fn load(&self) -> usize {
+        self.channels.len() + self.joining.len()
+    }
+
+    fn spawn(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        self.tasks.retain(|task| !task.is_finished());
+        let span = info_span!("shard", id = self.id);
+        self.tasks.push(tokio::spawn(task.instrument(span)));
+    }
+
+    /// Queues `outbound` for [`deliver`], so the manager never waits on a
+    /// connection that is busy reconnecting.
+    fn send(&self, outbound: Outbound) {
+        if let Some(outbox) = &self.outbox {
+            let _ = outbox.unbounded_send(outbound);
+        }
+    }
+}
+
+impl Drop for Shard {
+    fn drop(&mut self) {
+        // the reader and the senders keep the connection open, dropping
+        // them closes it
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// What the tasks of a shard report to the [`Manager`].
+enum Progress<L> {
+    Opened(
+        usize,
+        Result<Box<TwitchIrc<OpenedConnection, L>>, TrirkError>,
+    ),
+    Joined(usize, ChannelName, Result<(), TrirkError>),
+    /// The connection closed for good or keeps failing to reconnect.
+    Failed(usize),
+}
+
+/// Owns the connections of a [`TwitchPool`] and assigns channels to them.
+/// Connecting, joining and sending run in the shards' tasks, so a slow
+/// connection never holds up the other requests.
+struct Manager<L> {
+    configuration: TwitchConfig,
+    connect: fn(TwitchConfig) -> Result<TwitchIrc<ClosedConnection, L>, TrirkError>,
+    shards: Vec<Shard>,
+    next_shard: usize,
+    /// Twitch limits messages and joins per account, not per connection.
+    limiter: SharedLimiter,
+    incoming: mpsc::UnboundedSender<Result<PooledMessage, TrirkError>>,
+    progress: mpsc::UnboundedSender<Progress<L>>,
+}
+
+impl<L: Send + 'static> Manager<L> {
+    async fn run(
+        mut self,
+        mut requests: mpsc::Receiver<Request>,
+        mut progress: mpsc::UnboundedReceiver<Progress<L>>,
+    ) {
+        loop {
+            tokio::select! {
+                request = requests.next() => match request {
+                    Some(request) => self.handle(request),
+                    None => return,
+                },
+                Some(progress) = progress.next() => match progress {
+                    Progress::Opened(id, result) => self.opened(id, result),
+                    Progress::Joined(id, channel, result) => self.joined(id, channel, result),
+                    Progress::Failed(id) => self.rebalance(id),
+                },
+            }
+        }
+    }
+
+    fn handle(&mut self, request: Request) {
+        match request {
+            Request::Join(channel, reply) => self.join(channel, vec![reply]),
+            Request::Part(channel, reply) => self.part(channel, reply),
+            Request::Privmsg(channel, message, reply) => match self.shard_of(&channel) {
+                Some(shard) => shard.send(Outbound::Privmsg(channel, message, reply)),
+                None => {
+                    let _ = reply.send(Err(TrirkError::new(
+                        TrirkErrorKind::NotJoined,
+                        format!("{channel} is not joined"),
+                    )));
+                }
+            },
+            Request::Assignments(reply) => {
+                let _ = reply.send(
+                    self.shards
+                        .iter()
+                        .filter(|shard| shard.sender.is_some())
+                        .map(|shard| shard.channels.clone())
+                        .collect(),
+                );
+            }
+        }
+    }
+
+    fn index_of(&self, id: usize) -> Option<usize> {
+        self.shards.iter().position(|shard| shard.id == id)
+    }
+
+    fn shard_of(&mut self, channel: &ChannelName) -> Option<&mut Shard> {
+        self.shards
+            .iter_mut()
+            .find(|shard| shard.channels.contains(channel))
+    }
+
+    /// Joins `channel` on the least loaded connection, or adds `waiters`
+    /// to its join already in flight.
+    fn join(&mut self, channel: ChannelName, waiters: Vec<Reply<()>>) {
+        if self.shard_of(&channel).is_some() {
+            self.resolve(waiters, Ok(()));
+            return;
+        }
+        let pending = self
+            .shards
+            .iter_mut()
+            .flat_map(|shard| &mut shard.joining)
+            .find(|(joining, _)| joining == &channel);
+        if let Some((_, pending)) = pending {
+            pending.extend(waiters);
+            return;
+        }
+        let limit = self.configuration.channels_per_connection;
+        let least_loaded = self
+            .shards
+            .iter()
+            .enumerate()
+            .filter(|(_, shard)| shard.load() < limit)
+            .min_by_key(|(_, shard)| shard.load())
+            .map(|(index, _)| index);
+        let index = least_loaded.unwrap_or_else(|| self.open_shard());
+        self.shards[index].joining.push((channel.clone(), waiters));
+        // joins of an opening connection are sent once it is open
+        if self.shards[index].sender.is_some() {
+            self.send_join(index, channel);
+        }
+    }
+
+    /// Joins in a task of their own, as a join waits for Twitch's answer.
+    /// The shared limiter spaces them out across the shards.
+    fn send_join(&mut self, index: usize, channel: ChannelName) {
+        let shard = &mut self.shards[index];
+        let Some(mut sender) = shard.sender.clone() else {
+            return;
+        };
+        let (id, progress) = (shard.id, self.progress.clone());
+        shard.spawn(async move {
+            let result = sender.join(channel.clone()).await;
+            let _ = progress.unbounded_send(Progress::Joined(id, channel, result));
+        });
+    }
+
+    fn joined(&mut self, id: usize, channel: ChannelName, result: Result<(), TrirkError>) {
+        let Some(index) = self.index_of(id) else {
+            return;
+        };
+        let shard = &mut self.shards[index];
+        let Some(position) = shard
+            .joining
+            .iter()
+            .position(|(joining, _)| joining == &channel)
+        else {
+            // parted while joining
+            if result.is_ok() {
+                shard.send(Outbound::Part(channel, None));
+            }
+            return;
+        };
+        if result
+            .as_ref()
+            .is_err_and(|err| err.kind() == &TrirkErrorKind::ConnectionClosed)
+        {
+            self.rebalance(id);
+            return;
+        }
+        let (channel, waiters) = shard.joining.remove(position);
+        if result.is_ok() {
+            shard.channels.push(channel);
+        }
+        self.resolve(waiters, result);
+    }
+
+    fn part(&mut self, channel: ChannelName, reply: Reply<()>) {
+        for shard in &mut self.shards {
+            let Some(position) = shard
+                .joining
+                .iter()
+                .position(|(joining, _)| joining == &channel)
+            else {
+                continue;
+            };
+            // the PART is sent once the join is confirmed, see Manager::joined
+            let (_, waiters) = shard.joining.remove(position);
+            let parted = TrirkError::new(
+                TrirkErrorKind::JoinRejected,
+                format!("{channel} was parted before the join completed"),
+            );
+            self.resolve(waiters, Err(parted));
+            let _ = reply.send(Ok(()));
+            return;
+        }
+        let Some(shard) = self.shard_of(&channel) else {
+            let _ = reply.send(Ok(()));
+            return;
+        };
+        shard.channels.retain(|joined| joined != &channel);
+        shard.send(Outbound::Part(channel, Some(reply)));
+    }
+
+    /// Answers everyone waiting for a join, reporting a failure nobody
+    /// waits for, e.g. of a rebalanced channel, on the pool's stream.
+    fn resolve(&self, waiters: Vec<Reply<()>>, result: Result<(), TrirkError>) {
+        if waiters.is_empty() {
+            if let Err(err) = result {
+                let _ = self.incoming.unbounded_send(Err(err));
+            }
+            return;
+        }
+        for waiter in waiters {
+            let _ = waiter.send(result.as_ref().map_err(TrirkError::duplicate).copied());
+        }
+    }
+
+    /// Adds a connection, opened in the background, and returns its index.
+    fn open_shard(&mut self) -> usize {
+        let id = self.next_shard;
+        self.next_shard += 1;
+        let (connect, configuration) = (self.connect, self.configuration.clone());
+        let (limiter, progress) = (self.limiter.clone(), self.progress.clone());
+        let mut shard = Shard {
+            id,
+            sender: None,
+            outbox: None,
+            channels: vec![],
+            joining: vec![],
+            tasks: vec![],
+        };
+        shard.spawn(async move {
+            let result = match connect(configuration) {
+                Ok(irc) => irc
+                    .with_limiter(limiter)
+                    .open_connection()
+                    .await
+                    .map(Box::new),
+                Err(err) => Err(err),
+            };
+            let _ = progress.unbounded_send(Progress::Opened(id, result));
+        });
+        self.shards.push(shard);
+        self.shards.len() - 1
+    }
+
+    fn opened(
+        &mut self,
+        id: usize,
+        result: Result<Box<TwitchIrc<OpenedConnection, L>>, TrirkError>,
+    ) {
+        let Some(index) = self.index_of(id) else {
+            return;
+        };
+        let irc = match result {
+            Ok(irc) => irc,
+            Err(err) => {
+                warn!(shard = id, error = %err, "could not open a connection");
+                let joining = std::mem::take(&mut self.shards.remove(index).joining);
+                for (_, waiters) in joining {
+                    self.resolve(waiters, Err(err.duplicate()));
+                }
+                return;
+            }
+        };
+        let lifecycle = irc.lifecycle();
+        let (reader, sender) = irc.split();
+        let forward = forward(
+            id,
+            reader,
+            lifecycle,
+            self.incoming.clone(),
+            self.progress.clone(),
+        );
+        let sender = sender.retype();
+        let (outbox, outbound) = mpsc::unbounded();
+        let shard = &mut self.shards[index];
+        shard.spawn(forward);
+        shard.spawn(deliver(sender.clone(), outbound));
+        shard.sender = Some(sender);
+        shard.outbox = Some(outbox);
+        let channels: Vec<_> = shard
+            .joining
+            .iter()
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        for channel in channels {
+            self.send_join(index, channel);
+        }
+    }
+
+    /// Moves the channels of a failing connection to the others.
+    fn rebalance(&mut self, id: usize) {
+        let Some(index) = self.index_of(id) else {
+            return;
+        };
+        let mut shard = self.shards.remove(index);
+        warn!(
+            shard = id,
+            channels = ?shard.channels,
+            "connection failing, joining its channels on the others"
+        );
+        let channels = std::mem::take(&mut shard.channels);
+        let joining = std::mem::take(&mut shard.joining);
+        drop(shard);
+        for channel in channels {
+            self.join(channel, vec![]);
+        }
+        for (channel, waiters) in joining {
+            self.join(channel, waiters);
+        }
+    }
+}
+
+/// Makes the sends of a shard in order on its connection.
+async fn deliver(mut sender: Sender, mut outbound: mpsc::UnboundedReceiver<Outbound>) {
+    while let Some(outbound) = outbound.next().await {
+        match outbound {
+            Outbound::Privmsg(channel, message, reply) => {
+                let _ = reply.send(sender.privmsg_to(&channel, &message).await);
+            }
+            Outbound::Part(channel, reply) => {
+                let result = sender.part(&channel).await;
+                if let Some(reply) = reply {
+                    let _ = reply.send(result);
+                }
+            }
+        }
+    }
+}
+
+/// Forwards what shard `id` reads to the pool until its connection closes
+/// or fails [`REBALANCE_AFTER_FAILED_RECONNECTS`] reconnects in a row.
+async fn forward<L>(
+    id: usize,
+    mut reader: Reader,
+    mut lifecycle: broadcast::Receiver<ConnectionEvent>,
+    incoming: mpsc::UnboundedSender<Result<PooledMessage, TrirkError>>,
+    progress: mpsc::UnboundedSender<Progress<L>>,
+) {
+    let mut listening = true;
+    loop {
+        tokio::select! {
+            item = reader.next() => {
+                let Some(item) = item else {
+                    break;
+                };
+                let item = item.map(|message| PooledMessage {
+                    channel: message.command().channel().clone(),
+                    message,
+                });
+                let _ = incoming.unbounded_send(item);
+            }
+            event = lifecycle.recv(), if listening => match event {
+                Ok(ConnectionEvent::Reconnecting { attempt, .. })
+                    if attempt > REBALANCE_AFTER_FAILED_RECONNECTS =>
+                {
+                    break;
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => listening = false,
+            },
+        }
+    }
+    let _ = progress.unbounded_send(Progress::Failed(id));
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use mock_server::{MockServer, Script};
+    use parser::ChannelName;
+
+    use crate::{
+        error::TrirkErrorKind,
+        twitch_irc::{config::TwitchConfig, reconnect::ReconnectPolicy, tests::local_builder},
+    };
+
+    use super::TwitchPool;
+
+    /// `names` spread `per_connection` to a connection, each closing for
+    /// good the first time it is dropped.
+    fn pool_config(server: &MockServer, names: &[&str], per_connection: usize) -> TwitchConfig {
+        local_builder(server)
+            .channels(channels(names))
+            .channels_per_connection(per_connection)
+            .reconnect(ReconnectPolicy {
+                max_attempts: Some(0),
+                ..Default::default()
+            })
+            .build()
+            .unwrap()
+    }
+
+    fn channels(names: &[&str]) -> Vec<ChannelName> {
+        names
+            .iter()
+            .map(|name| ChannelName::new(name).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn should_part_channels() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let mut pool = TwitchPool::open_anonymous(pool_config(&server, &["a", "b"], 2))
+            .await
+            .unwrap();
+        pool.part(&ChannelName::new("a").unwrap()).await.unwrap();
+        assert_eq!(vec![channels(&["b"])], pool.assignments().await.unwrap());
+        server.wait_for("PART #a").await;
+    }
+
+    #[tokio::test]
+    async fn should_refuse_messages_to_channels_not_joined() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let mut pool = TwitchPool::open(pool_config(&server, &["a"], 1))
+            .await
+            .unwrap();
+        let err = pool
+            .privmsg_to(&ChannelName::new("dallas").unwrap(), "hi")
+            .await
+            .unwrap_err();
+        assert_eq!(&TrirkErrorKind::NotJoined, err.kind());
+    }
+
+    #[tokio::test]
+    async fn should_share_the_account_message_limit_between_connections() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let mut pool = TwitchPool::open(pool_config(&server, &["a", "b"], 1))
+            .await
+            .unwrap();
+        assert_eq!(2, pool.assignments().await.unwrap().len());
+        let (a, b) = (
+            ChannelName::new("a").unwrap(),
+            ChannelName::new("b").unwrap(),
+        );
+        for index in 0..21 {
+            let channel = if index % 2 == 0 { &a } else { &b };
+            pool.privmsg_to(channel, "hi").await.unwrap();
+        }
+        let sent = |received: &[String]| {
+            received
+                .iter()
+                .filter(|line| line.starts_with("PRIVMSG"))
+                .count()
+        };
+        server.wait_until(|received| sent(received) >= 20).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // the 21st waits for the account's window, not its connection's
+        assert_eq!(20, sent(&server.received()));
+    }
+
+    #[tokio::test]
+    async fn should_join_elsewhere_when_a_connection_fails_while_joining() {
+        // the first connection joining #flaky closes without answering
+        let server = MockServer::start(Script::new().with_hung_up_join("flaky"))
+            .await
+            .unwrap();
+        let mut pool = TwitchPool::open_anonymous(pool_config(&server, &["a", "b"], 2))
+            .await
+            .unwrap();
+        pool.join(ChannelName::new("flaky").unwrap()).await.unwrap();
+        assert_eq!(
+            vec![channels(&["a", "b"]), channels(&["flaky"])],
+            pool.assignments().await.unwrap()
+        );
+        assert_eq!(3, server.connections());
+    }
+
+    #[tokio::test]
+    async fn should_spread_channels_and_rebalance_failed_connections() {
+        // the first connection joining #flaky is closed right after
+        let server = MockServer::start(Script::new().with_dropped_join("flaky"))
+            .await
+            .unwrap();
+        let config = pool_config(&server, &["a", "b", "c"], 2);
+        let mut pool = TwitchPool::open_anonymous(config).await.unwrap();
+        assert_eq!(
+            vec![channels(&["a", "b"]), channels(&["c"])],
+            pool.assignments().await.unwrap()
+        );
+        pool.join(ChannelName::new("flaky").unwrap()).await.unwrap();
+        let message = loop {
+            let message = pool.read_next().await.unwrap();
+            if message.channel.is_some() {
+                break message;
+            }
+        };
+        assert_eq!(Some(ChannelName::new("a").unwrap()), message.channel);
+        // #flaky's connection closes and c moves along with it
+        let expected = vec![channels(&["a", "b"]), channels(&["c", "flaky"])];
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.assignments().await.unwrap() != expected {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use parser::{ChannelName, CommandType, TwitchMessage};
+use tokio::time::Instant;
+
+const MESSAGE_PERIOD: Duration = Duration::from_secs(30);
+const REGULAR_MESSAGE_LIMIT: usize = 20;
+const PRIVILEGED_MESSAGE_LIMIT: usize = 100;
+const JOIN_LIMIT: usize = 20;
+const JOIN_PERIOD: Duration = Duration::from_secs(10);
+
+/// Allows at most `limit` actions in any `period` long window.
+#[derive(Debug)]
+pub struct SlidingWindow {
+    limit: usize,
+    period: Duration,
+    sent: VecDeque<Instant>,
+}
+
+impl SlidingWindow {
+    #[inline(always)]
+    pub fn new(limit: usize, period: Duration) -> Self {
+        Self {
+            limit,
+            period,
+            sent: VecDeque::with_capacity(limit),
+        }
+    }
+
+    /// Records an action if it is allowed right now.
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= self.period)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.limit {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+
+    /// When [`SlidingWindow::try_acquire`] allows the next action.
+    pub fn available_at(&self) -> Instant {
+        let now = Instant::now();
+        if self.sent.len() < self.limit {
+            return now;
+        }
+        (self.sent[self.sent.len() - self.limit] + self.period).max(now)
+    }
+}
+
+/// One [`RateLimiter`] for every connection of an account.
+pub(super) type SharedLimiter = Arc<Mutex<RateLimiter>>;
+
+#[derive(Default, Debug)]
+struct ChannelState {
+    /// The bot is a moderator or the broadcaster, known from USERSTATE.
+    privileged: bool,
+    slow: Duration,
+    last_sent: Option<Instant>,
+}
+
+/// Keeps outgoing traffic within Twitch's limits: 20 messages per 30
+/// seconds, 100 in channels where the bot is a moderator or the
+/// broadcaster, the channel's slow mode and 20 joins per 10 seconds.
+///
+/// The connection queues what may not be sent yet until its turn, so
+/// messages are delayed instead of being silently dropped by Twitch.
+///
+/// The limits are per account: connections logged in to the same one,
+/// like those of a [`TwitchPool`](super::pool::TwitchPool), share one
+/// limiter.
+#[derive(Debug)]
+pub struct RateLimiter {
+    regular: SlidingWindow,
+    privileged: SlidingWindow,
+    joins: SlidingWindow,
+    channels: HashMap<ChannelName, ChannelState>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimiter {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self {
+            regular: SlidingWindow::new(REGULAR_MESSAGE_LIMIT, MESSAGE_PERIOD),
+            privileged: SlidingWindow::new(PRIVILEGED_MESSAGE_LIMIT, MESSAGE_PERIOD),
+            joins: SlidingWindow::new(JOIN_LIMIT, JOIN_PERIOD),
+            channels: HashMap::new(),
+        }
+    }
+
+    /// A limiter for connections of one account to share.
+    pub(super) fn shared() -> SharedLimiter {
+        Arc::new(Mutex::new(Self::new()))
+    }
+
+    /// Updates moderator status and slow mode from USERSTATE and ROOMSTATE.
+    pub fn observe(&mut self, message: &TwitchMessage) {
+        let (Some(channel), Some(tags)) = (message.command().channel(), message.tags()) else {
+            return;
+        };
+        match message.command().command() {
+            CommandType::UserState => {
+                let privileged = *tags.r#mod() || tags.badges().broadcaster().is_some();
+                self.channels.entry(channel.clone()).or_default().privileged = privileged;
+            }
+            CommandType::RoomState => {
+                if let Some(slow) = tags.slow() {
+                    self.channels.entry(channel.clone()).or_default().slow =
+                        Duration::from_secs(*slow as u64);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_privileged(&self, channel: &ChannelName) -> bool {
+        self.channels
+            .get(channel)
+            .is_some_and(|state| state.privileged)
+    }
+
+    /// Records a message to `channel` if it may be sent right now.
+    pub fn try_acquire_message(&mut self, channel: &ChannelName) -> bool {
+        let available_at = self.message_available_at(channel);
+        let now = Instant::now();
+        if available_at > now {
+            return false;
+        }
+        let state = self.channels.entry(channel.clone()).or_default();
+        // both windows have room, checked above
+        if !state.privileged {
+            self.regular.try_acquire();
+        }
+        self.privileged.try_acquire();
+        state.last_sent = Some(now);
+        true
+    }
+
+    /// When [`RateLimiter::try_acquire_message`] allows the next message
+    /// to `channel`.
+    pub fn message_available_at(&self, channel: &ChannelName) -> Instant {
+        let mut available_at = self.privileged.available_at();
+        if let Some(state) = self.channels.get(channel) {
+            if state.privileged {
+                return available_at;
+            }
+            if let Some(last_sent) = state.last_sent {
+                available_at = available_at.max(last_sent + state.slow);
+            }
+        }
+        available_at.max(self.regular.available_at())
+    }
+
+    /// When [`RateLimiter::try_acquire_join`] allows the next JOIN.
+    pub fn join_available_at(&self) -> Instant {
+        self.joins.available_at()
+    }
+
+    /// Records a JOIN if it may be sent right now.
+    pub fn try_acquire_join(&mut self) -> bool {
+        self.joins.try_acquire()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use parser::{trirk_parser::TrirkParser, ChannelName};
+    use tokio::time::{advance, Instant};
+
+    use super::{RateLimiter, SlidingWindow};
+
+    fn dallas() -> ChannelName {
+        ChannelName::new("dallas").unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_free_a_slot_once_the_period_passes() {
+        let start = Instant::now();
+        let mut window = SlidingWindow::new(2, Duration::from_secs(10));
+        assert!(window.try_acquire());
+        assert!(window.try_acquire());
+        assert!(!window.try_acquire());
+        assert_eq!(start + Duration::from_secs(10), window.available_at());
+        advance(Duration::from_secs(10)).await;
+        assert!(window.try_acquire());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_queue_messages_past_the_regular_limit() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new();
+        for _ in 0..20 {
+            assert!(limiter.try_acquire_message(&dallas()));
+        }
+        assert!(!limiter.try_acquire_message(&dallas()));
+        assert_eq!(
+            start + Duration::from_secs(30),
+            limiter.message_available_at(&dallas())
+        );
+        advance(Duration::from_secs(30)).await;
+        assert!(limiter.try_acquire_message(&dallas()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_use_higher_limit_and_skip_slow_mode_as_moderator() {
+        let parser = TrirkParser::new();
+        let mut limiter = RateLimiter::new();
+        limiter.observe(
+            &parser
+                .parse("@slow=10 :tmi.twitch.tv ROOMSTATE #dallas")
+                .unwrap(),
+        );
+        limiter.observe(
+            &parser
+                .parse("@badges=moderator/1;mod=1 :tmi.twitch.tv USERSTATE #dallas")
+                .unwrap(),
+        );
+        assert!(limiter.is_privileged(&dallas()));
+        for _ in 0..100 {
+            assert!(limiter.try_acquire_message(&dallas()));
+        }
+        assert!(!limiter.try_acquire_message(&dallas()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_respect_slow_mode() {
+        let parser = TrirkParser::new();
+        let mut limiter = RateLimiter::new();
+        limiter.observe(
+            &parser
+                .parse("@slow=10 :tmi.twitch.tv ROOMSTATE #dallas")
+                .unwrap(),
+        );
+        limiter.observe(
+            &parser
+                .parse("@emote-only=1 :tmi.twitch.tv ROOMSTATE #dallas")
+                .unwrap(),
+        );
+        let start = Instant::now();
+        assert!(limiter.try_acquire_message(&dallas()));
+        assert!(!limiter.try_acquire_message(&dallas()));
+        assert_eq!(
+            start + Duration::from_secs(10),
+            limiter.message_available_at(&dallas())
+        );
+        assert!(limiter.try_acquire_message(&ChannelName::new("ronni").unwrap()));
+        advance(Duration::from_secs(10)).await;
+        assert!(limiter.try_acquire_message(&dallas()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_space_out_joins() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new();
+        for _ in 0..20 {
+            assert!(limiter.try_acquire_join());
+        }
+        assert!(!limiter.try_acquire_join());
+        assert_eq!(start + Duration::from_secs(10), limiter.join_available_at());
+    }
+}
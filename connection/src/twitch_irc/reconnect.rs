@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+/// Exponential backoff used when the connection has to be re-established.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Gives up after this many failed attempts, `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(120),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before the given attempt (starting at 1), doubled on every
+    /// attempt and jittered down to half of it so many bots dropped at once
+    /// do not reconnect in lockstep. `None` once the attempts are exhausted.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt > max) {
+            return None;
+        }
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .initial_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let half = delay / 2;
+        Some(half + half.mul_f64(fastrand::f64()))
+    }
+}
+
+/// Connection lifecycle, reported through [`TwitchIrc::lifecycle`](super::TwitchIrc::lifecycle).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Connected,
+    /// `reason` is the error or server request that ended the connection.
+    Disconnected {
+        reason: String,
+    },
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+        /// Why the previous attempt failed, `None` on the first one.
+        last_error: Option<String>,
+    },
+}
+
+/// Publishes `event` to the lifecycle subscribers, if there are any.
+pub(super) fn notify(lifecycle: &broadcast::Sender<ConnectionEvent>, event: ConnectionEvent) {
+    // nobody listening is fine
+    let _ = lifecycle.send(event);
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ReconnectPolicy;
+
+    #[test]
+    fn should_back_off_exponentially_with_jitter() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_attempts: None,
+        };
+        for (attempt, expected) in [(1, 1), (2, 2), (3, 4), (4, 8), (5, 10), (40, 10)] {
+            let expected = Duration::from_secs(expected);
+            let delay = policy.delay(attempt).unwrap();
+            assert!(delay >= expected / 2 && delay <= expected, "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn should_give_up_after_max_attempts() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(2),
+            ..Default::default()
+        };
+        assert!(policy.delay(2).is_some());
+        assert_eq!(None, policy.delay(3));
+    }
+}
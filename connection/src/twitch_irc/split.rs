@@ -0,0 +1,185 @@
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    Sink, SinkExt, Stream, StreamExt,
+};
+use parser::{ChannelName, TwitchMessage};
+use tokio::sync::watch;
+
+use crate::error::TrirkError;
+
+use super::{
+    driver::{Incoming, Request, Status},
+    outgoing::Outgoing,
+    Authenticated,
+};
+
+/// Cheap, cloneable handle sending to a connection from any task. Requests
+/// are queued to the task driving the connection, which writes them in
+/// order within Twitch's rate limits.
+pub struct Sender<L = Authenticated> {
+    requests: mpsc::Sender<Request>,
+    status: watch::Receiver<Status>,
+    _marker: PhantomData<L>,
+}
+
+impl<L> Clone for Sender<L> {
+    fn clone(&self) -> Self {
+        Self {
+            requests: self.requests.clone(),
+            status: self.status.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<L> Sender<L> {
+    pub(super) fn new(requests: mpsc::Sender<Request>, status: watch::Receiver<Status>) -> Self {
+        Self {
+            requests,
+            status,
+            _marker: PhantomData,
+        }
+    }
+
+    pub(super) fn retype<M>(self) -> Sender<M> {
+        Sender::new(self.requests, self.status)
+    }
+
+    async fn request(&mut self, request: Request) -> Result<(), TrirkError> {
+        self.requests
+            .send(request)
+            .await
+            .map_err(|_| TrirkError::closed("connection"))
+    }
+
+    /// Joins `channel`, resolving once Twitch confirms it with a ROOMSTATE.
+    /// Fails when Twitch refuses the join (e.g. the channel is suspended)
+    /// or does not answer in time. Messages keep arriving meanwhile.
+    pub async fn join(&mut self, channel: ChannelName) -> Result<(), TrirkError> {
+        let (reply, confirmation) = oneshot::channel();
+        self.request(Request::Join(channel, reply)).await?;
+        confirmation
+            .await
+            .map_err(|_| TrirkError::closed("connection"))?
+    }
+
+    pub async fn part(&mut self, channel: &ChannelName) -> Result<(), TrirkError> {
+        self.request(Request::Send(Outgoing::Part(channel.clone())))
+            .await
+    }
+
+    /// Channels Twitch confirmed joining, rejoined automatically after a
+    /// reconnect.
+    pub fn channels(&self) -> Vec<ChannelName> {
+        self.status.borrow().channels.clone()
+    }
+
+    /// Round trip of the last keepalive PING, `None` until one was answered.
+    pub fn latency(&self) -> Option<Duration> {
+        self.status.borrow().latency
+    }
+}
+
+impl Sender<Authenticated> {
+    /// Queues raw bytes, written as is without rate limiting.
+    pub async fn send_bytes(&mut self, message: &[u8]) -> Result<(), TrirkError> {
+        self.request(Request::Send(Outgoing::Raw(message.to_vec())))
+            .await
+    }
+
+    /// Queues `message` for `channel`. It is written once Twitch's rate
+    /// limits allow it (see [`RateLimiter`](super::rate_limit::RateLimiter)).
+    /// Line breaks are replaced by spaces and messages longer than
+    /// [`MAX_MESSAGE_LENGTH`](super::outgoing::MAX_MESSAGE_LENGTH) are sent
+    /// as several PRIVMSGs.
+    pub async fn privmsg_to(
+        &mut self,
+        channel: &ChannelName,
+        message: &str,
+    ) -> Result<(), TrirkError> {
+        self.request(Request::Send(Outgoing::Privmsg {
+            channel: channel.clone(),
+            text: message.into(),
+        }))
+        .await
+    }
+}
+
+impl Sink<Outgoing> for Sender<Authenticated> {
+    type Error = TrirkError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.requests
+            .poll_ready(cx)
+            .map_err(|_| TrirkError::closed("connection"))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Outgoing) -> Result<(), Self::Error> {
+        self.requests
+            .start_send(Request::Send(item))
+            .map_err(|_| TrirkError::closed("connection"))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.requests
+            .poll_flush_unpin(cx)
+            .map_err(|_| TrirkError::closed("connection"))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.requests
+            .poll_close_unpin(cx)
+            .map_err(|_| TrirkError::closed("connection"))
+    }
+}
+
+/// Reading half of a split connection, see
+/// [`TwitchIrc::split`](super::TwitchIrc::split).
+pub struct Reader {
+    incoming: mpsc::UnboundedReceiver<Incoming>,
+    /// Keeps the connection open while only the reader is left.
+    _requests: mpsc::Sender<Request>,
+}
+
+impl Reader {
+    pub(super) fn new(
+        incoming: mpsc::UnboundedReceiver<Incoming>,
+        requests: mpsc::Sender<Request>,
+    ) -> Self {
+        Self {
+            incoming,
+            _requests: requests,
+        }
+    }
+
+    /// Reads the next message. A closed socket, a read error, a missed
+    /// keepalive PONG or a RECONNECT from Twitch transparently
+    /// re-establishes the connection following the configured
+    /// [`ReconnectPolicy`](super::reconnect::ReconnectPolicy). An error is
+    /// returned for unparsable or oversized lines, for a message Twitch
+    /// dropped as sent too quickly
+    /// ([`TrirkErrorKind::RateLimited`](crate::error::TrirkErrorKind::RateLimited),
+    /// followed by Twitch's NOTICE) and once the policy gives up.
+    pub async fn read_next(&mut self) -> Result<TwitchMessage, TrirkError> {
+        self.incoming
+            .next()
+            .await
+            .unwrap_or_else(|| Err(TrirkError::closed("connection")))
+    }
+}
+
+/// Incoming messages, ending once the connection is closed for good.
+impl Stream for Reader {
+    type Item = Result<TwitchMessage, TrirkError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming.poll_next_unpin(cx)
+    }
+}
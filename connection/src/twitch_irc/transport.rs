@@ -0,0 +1,67 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    sync::Arc,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+
+use super::config::TwitchConfig;
+
+/// Byte stream the IRC connection runs over, plaintext or TLS.
+pub trait IrcStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IrcStream for T {}
+
+pub(super) async fn connect(configuration: &TwitchConfig) -> Result<Box<dyn IrcStream>> {
+    tokio::time::timeout(configuration.connect_timeout, open(configuration))
+        .await
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::TimedOut,
+                "timed out connecting to the IRC server",
+            )
+        })?
+}
+
+async fn open(configuration: &TwitchConfig) -> Result<Box<dyn IrcStream>> {
+    let host = configuration.host.as_str();
+    let stream = TcpStream::connect((host, configuration.port())).await?;
+    stream.set_nodelay(true)?;
+    if !configuration.tls {
+        return Ok(Box::new(stream));
+    }
+    let connector = TlsConnector::from(Arc::new(tls_config(configuration)?));
+    let server_name = ServerName::try_from(host.to_owned())
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    let stream = connector.connect(server_name, stream).await?;
+    Ok(Box::new(stream))
+}
+
+fn tls_config(configuration: &TwitchConfig) -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    match &configuration.ca_certificate {
+        Some(pem) => {
+            for certificate in CertificateDer::pem_slice_iter(pem) {
+                let certificate =
+                    certificate.map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+                roots
+                    .add(certificate)
+                    .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    Ok(ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
@@ -0,0 +1,451 @@
+//! Scriptable local stand-in for Twitch's IRC server, so clients can be
+//! tested without a live connection. [`MockServer::start_tls`] serves it
+//! over TLS with a self-signed certificate.
+//!
+//! ```no_run
+//! # async fn example() -> std::io::Result<()> {
+//! use mock_server::{messages, MockServer, Script};
+//!
+//! let server = MockServer::start(Script::new().with_suspended_channel("banned")).await?;
+//! // point the client at server.host():server.port(), then
+//! server.wait_for("JOIN #dallas").await;
+//! server.send(&messages::privmsg("dallas", "ronni", "hi"));
+//! server.reconnect();
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod messages;
+mod script;
+mod session;
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+pub use script::Script;
+
+use session::Session;
+
+const HOST: &str = "127.0.0.1";
+/// How long [`MockServer::wait_until`] waits before failing the test.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the test asks a live connection to do.
+enum Control {
+    Send(String),
+    Close,
+}
+
+struct Shared {
+    script: Mutex<Script>,
+    /// Every line the clients sent, across connections, in arrival order.
+    received: Mutex<Vec<String>>,
+    /// Bumped on each received line so waiters re-check their condition.
+    activity: watch::Sender<()>,
+    clients: Mutex<Vec<mpsc::UnboundedSender<Control>>>,
+    connections: Mutex<usize>,
+    /// Channels of [`Script::with_dropped_join`] and
+    /// [`Script::with_hung_up_join`] whose connection was already closed.
+    closed_joins: Mutex<Vec<String>>,
+}
+
+impl Shared {
+    fn script(&self) -> MutexGuard<'_, Script> {
+        self.script.lock().unwrap()
+    }
+
+    fn receive(&self, line: &str) {
+        self.received.lock().unwrap().push(line.into());
+        self.activity.send_replace(());
+    }
+
+    /// Whether joining `channel` should close the connection after
+    /// confirming it, true only once.
+    fn drop_join(&self, channel: &str) -> bool {
+        let scripted = self
+            .script()
+            .dropped_joins
+            .iter()
+            .any(|name| name == channel);
+        scripted && self.close_join_once(channel)
+    }
+
+    /// Whether joining `channel` should close the connection without
+    /// answering, true only once.
+    fn hang_up_join(&self, channel: &str) -> bool {
+        let scripted = self
+            .script()
+            .hung_up_joins
+            .iter()
+            .any(|name| name == channel);
+        scripted && self.close_join_once(channel)
+    }
+
+    fn close_join_once(&self, channel: &str) -> bool {
+        let mut closed = self.closed_joins.lock().unwrap();
+        if closed.iter().any(|name| name == channel) {
+            return false;
+        }
+        closed.push(channel.into());
+        true
+    }
+
+    fn control(&self, control: impl Fn() -> Control) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send(control()).is_ok());
+    }
+}
+
+/// Twitch IRC stand-in listening on a local port. It answers the
+/// handshake, JOIN, PART, PING and PRIVMSG like Twitch does, following its
+/// [`Script`], and records every line the clients send.
+///
+/// Dropping the server closes its connections.
+pub struct MockServer {
+    address: SocketAddr,
+    shared: Arc<Shared>,
+    accepting: JoinHandle<()>,
+    /// PEM of the self-signed certificate when serving over TLS.
+    certificate: Option<String>,
+}
+
+impl MockServer {
+    pub async fn start(script: Script) -> io::Result<Self> {
+        Self::listen(script, None).await
+    }
+
+    /// Like [`MockServer::start`], over TLS with a certificate for `HOST`
+    /// that clients only trust once given [`MockServer::certificate`].
+    pub async fn start_tls(script: Script) -> io::Result<Self> {
+        let invalid = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+        let certified = rcgen::generate_simple_self_signed(vec![HOST.into()])
+            .map_err(|err| invalid(err.to_string()))?;
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![certified.cert.der().clone()],
+                PrivateKeyDer::Pkcs8(key),
+            )
+            .map_err(|err| invalid(err.to_string()))?;
+        let mut server = Self::listen(script, Some(TlsAcceptor::from(Arc::new(config)))).await?;
+        server.certificate = Some(certified.cert.pem());
+        Ok(server)
+    }
+
+    async fn listen(script: Script, tls: Option<TlsAcceptor>) -> io::Result<Self> {
+        let listener = TcpListener::bind((HOST, 0)).await?;
+        let address = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            script: Mutex::new(script),
+            received: Mutex::new(vec![]),
+            activity: watch::channel(()).0,
+            clients: Mutex::new(vec![]),
+            connections: Mutex::new(0),
+            closed_joins: Mutex::new(vec![]),
+        });
+        let accepting = tokio::spawn(accept(listener, tls, shared.clone()));
+        Ok(Self {
+            address,
+            shared,
+            accepting,
+            certificate: None,
+        })
+    }
+
+    pub fn host(&self) -> &'static str {
+        HOST
+    }
+
+    pub fn port(&self) -> u16 {
+        self.address.port()
+    }
+
+    /// PEM encoded certificate of a server started with
+    /// [`MockServer::start_tls`], `None` for plaintext servers.
+    pub fn certificate(&self) -> Option<&str> {
+        self.certificate.as_deref()
+    }
+
+    /// Connections accepted so far, reconnections included.
+    pub fn connections(&self) -> usize {
+        *self.shared.connections.lock().unwrap()
+    }
+
+    /// Lines received from every connection so far, without line endings.
+    pub fn received(&self) -> Vec<String> {
+        self.shared.received.lock().unwrap().clone()
+    }
+
+    /// Replaces the script for the lines received from now on, e.g. to
+    /// reject the login once the client reconnects.
+    pub fn set_script(&self, script: Script) {
+        *self.shared.script() = script;
+    }
+
+    /// Sends a raw line, without its line ending, to every open connection.
+    pub fn send(&self, line: &str) {
+        self.shared.control(|| Control::Send(line.into()));
+    }
+
+    /// Asks every client to reconnect like Twitch does before a restart,
+    /// then closes the connections.
+    pub fn reconnect(&self) {
+        self.send(":tmi.twitch.tv RECONNECT");
+        self.disconnect();
+    }
+
+    /// Closes every open connection without notice.
+    pub fn disconnect(&self) {
+        self.shared.control(|| Control::Close);
+    }
+
+    /// Waits until `condition` holds for the lines received so far.
+    ///
+    /// # Panics
+    ///
+    /// After 5 seconds, so a client that never sends the expected lines
+    /// fails the test instead of hanging it.
+    pub async fn wait_until(&self, condition: impl Fn(&[String]) -> bool) {
+        let mut activity = self.shared.activity.subscribe();
+        let waiting = async {
+            loop {
+                if condition(&self.shared.received.lock().unwrap()) {
+                    return;
+                }
+                // the sender lives as long as the server
+                let _ = activity.changed().await;
+            }
+        };
+        if tokio::time::timeout(WAIT_TIMEOUT, waiting).await.is_err() {
+            panic!("condition not met, received {:?}", self.received());
+        }
+    }
+
+    /// Waits for a received line starting with `prefix` and returns the
+    /// first one. Panics like [`MockServer::wait_until`].
+    pub async fn wait_for(&self, prefix: &str) -> String {
+        let matches = |line: &&String| line.starts_with(prefix);
+        self.wait_until(|received| received.iter().any(|line| matches(&line)))
+            .await;
+        self.received()
+            .iter()
+            .find(matches)
+            .cloned()
+            .expect("waited for it")
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.accepting.abort();
+        self.disconnect();
+    }
+}
+
+async fn accept(listener: TcpListener, tls: Option<TlsAcceptor>, shared: Arc<Shared>) {
+    while let Ok((socket, _)) = listener.accept().await {
+        *shared.connections.lock().unwrap() += 1;
+        let (control, controls) = mpsc::unbounded_channel();
+        shared.clients.lock().unwrap().push(control);
+        let shared = shared.clone();
+        match tls.clone() {
+            Some(tls) => tokio::spawn(async move {
+                // clients that do not trust the certificate end here
+                if let Ok(socket) = tls.accept(socket).await {
+                    serve(socket, shared, controls).await;
+                }
+            }),
+            None => tokio::spawn(serve(socket, shared, controls)),
+        };
+    }
+}
+
+async fn serve(
+    socket: impl AsyncRead + AsyncWrite,
+    shared: Arc<Shared>,
+    mut controls: mpsc::UnboundedReceiver<Control>,
+) {
+    let (reader, mut writer) = tokio::io::split(socket);
+    let mut lines = BufReader::new(reader).lines();
+    let mut session = Session::new();
+    loop {
+        let (replies, close) = tokio::select! {
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else {
+                    return;
+                };
+                shared.receive(&line);
+                session.respond(&shared, &line)
+            }
+            control = controls.recv() => match control {
+                Some(Control::Send(line)) => (vec![line], false),
+                Some(Control::Close) | None => return,
+            },
+        };
+        for reply in replies {
+            if writer
+                .write_all(format!("{reply}\r\n").as_bytes())
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        if close {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpStream,
+        },
+    };
+
+    use super::{messages, MockServer, Script};
+
+    struct Client {
+        lines: Lines<BufReader<OwnedReadHalf>>,
+        writer: OwnedWriteHalf,
+    }
+
+    impl Client {
+        async fn connect(server: &MockServer) -> Self {
+            let socket = TcpStream::connect((server.host(), server.port()))
+                .await
+                .unwrap();
+            let (reader, writer) = socket.into_split();
+            Self {
+                lines: BufReader::new(reader).lines(),
+                writer,
+            }
+        }
+
+        async fn send(&mut self, line: &str) {
+            let line = format!("{line}\r\n");
+            self.writer.write_all(line.as_bytes()).await.unwrap();
+        }
+
+        /// Reads until a line containing `needle`, `None` once closed.
+        async fn read_until(&mut self, needle: &str) -> Option<String> {
+            while let Some(line) = self.lines.next_line().await.unwrap() {
+                if line.contains(needle) {
+                    return Some(line);
+                }
+            }
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn should_answer_the_handshake_and_joins() {
+        let server = MockServer::start(
+            Script::new()
+                .with_rejected_capability("bogus")
+                .with_suspended_channel("banned")
+                .with_join_line("dallas", &messages::privmsg("dallas", "ronni", "hi")),
+        )
+        .await
+        .unwrap();
+        let mut client = Client::connect(&server).await;
+        client.send("NICK trirk").await;
+        client.read_until(" 001 trirk ").await.unwrap();
+        client
+            .send("CAP REQ :twitch.tv/tags twitch.tv/commands")
+            .await;
+        client
+            .read_until("CAP * ACK :twitch.tv/tags twitch.tv/commands")
+            .await
+            .unwrap();
+        client.send("CAP REQ :twitch.tv/bogus").await;
+        client
+            .read_until("CAP * NAK :twitch.tv/bogus")
+            .await
+            .unwrap();
+        client.send("JOIN #dallas,#banned").await;
+        client.read_until("ROOMSTATE #dallas").await.unwrap();
+        let hi = client.read_until("PRIVMSG").await.unwrap();
+        assert!(hi.ends_with(" :ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #dallas :hi"));
+        let refused = client.read_until("NOTICE #banned").await.unwrap();
+        assert!(refused.starts_with("@msg-id=msg_channel_suspended "));
+        assert_eq!(
+            vec![
+                "NICK trirk",
+                "CAP REQ :twitch.tv/tags twitch.tv/commands",
+                "CAP REQ :twitch.tv/bogus",
+                "JOIN #dallas,#banned",
+            ],
+            server.received()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_logins() {
+        let server = MockServer::start(Script::new().with_rejected_login())
+            .await
+            .unwrap();
+        let mut client = Client::connect(&server).await;
+        client.send("PASS oauth:token").await;
+        client.send("NICK trirk").await;
+        client
+            .read_until("Login authentication failed")
+            .await
+            .unwrap();
+        assert_eq!(None, client.read_until("").await);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_messages_over_the_limit() {
+        let server =
+            MockServer::start(Script::new().with_message_limit(1, Duration::from_secs(30)))
+                .await
+                .unwrap();
+        let mut client = Client::connect(&server).await;
+        client.send("PRIVMSG #dallas :one").await;
+        client.send("PRIVMSG #dallas :two").await;
+        let refused = client.read_until("NOTICE").await.unwrap();
+        assert!(refused.starts_with("@msg-id=msg_ratelimit "));
+    }
+
+    #[tokio::test]
+    async fn should_push_lines_and_close_connections() {
+        let server = MockServer::start(Script::new()).await.unwrap();
+        let mut client = Client::connect(&server).await;
+        client.send("PING :tmi.twitch.tv").await;
+        client.read_until("PONG").await.unwrap();
+        server.send("@ban-duration=10 :tmi.twitch.tv CLEARCHAT #dallas :ronni");
+        client.read_until("CLEARCHAT").await.unwrap();
+        server.reconnect();
+        client.read_until("RECONNECT").await.unwrap();
+        assert_eq!(None, client.read_until("").await);
+        assert_eq!(1, server.connections());
+    }
+}
@@ -0,0 +1,90 @@
+//! Tagged lines shaped like the ones Twitch sends, to push through
+//! [`MockServer::send`](crate::MockServer::send) or a [`Script`](crate::Script).
+//! Channels are named without their `#`; ids are stable per name.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static MESSAGE_IDS: AtomicU64 = AtomicU64::new(1);
+
+/// Numeric id derived from `name`, the same on every call.
+fn numeric_id(name: &str) -> u64 {
+    // FNV-1a
+    let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    10_000_000 + hash % 90_000_000
+}
+
+fn message_id() -> String {
+    let id = MESSAGE_IDS.fetch_add(1, Ordering::Relaxed);
+    format!("00000000-0000-4000-8000-{id:012x}")
+}
+
+fn sent_at() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis())
+}
+
+pub fn privmsg(channel: &str, user: &str, text: &str) -> String {
+    format!(
+        "@badge-info=;badges=;color=#1E90FF;display-name={user};emotes=;first-msg=0;flags=;\
+         id={id};mod=0;returning-chatter=0;room-id={room_id};subscriber=0;tmi-sent-ts={sent_at};\
+         turbo=0;user-id={user_id};user-type= :{user}!{user}@{user}.tmi.twitch.tv PRIVMSG #{channel} :{text}",
+        id = message_id(),
+        room_id = numeric_id(channel),
+        sent_at = sent_at(),
+        user_id = numeric_id(user),
+    )
+}
+
+/// A `sub` USERNOTICE for the first month, a `resub` afterwards.
+pub fn subscription(channel: &str, user: &str, months: u32) -> String {
+    let kind = if months > 1 { "resub" } else { "sub" };
+    format!(
+        "@badge-info=subscriber/{months};badges=subscriber/0;color=;display-name={user};emotes=;\
+         flags=;id={id};login={user};mod=0;msg-id={kind};msg-param-cumulative-months={months};\
+         msg-param-sub-plan=1000;room-id={room_id};subscriber=1;\
+         system-msg={user}\\ssubscribed\\sat\\sTier\\s1.;tmi-sent-ts={sent_at};\
+         user-id={user_id};user-type= :tmi.twitch.tv USERNOTICE #{channel}",
+        id = message_id(),
+        room_id = numeric_id(channel),
+        sent_at = sent_at(),
+        user_id = numeric_id(user),
+    )
+}
+
+/// CLEARCHAT timing `user` out for `seconds`.
+pub fn timeout(channel: &str, user: &str, seconds: u64) -> String {
+    format!(
+        "@ban-duration={seconds};room-id={room_id};target-user-id={user_id};tmi-sent-ts={sent_at} \
+         :tmi.twitch.tv CLEARCHAT #{channel} :{user}",
+        room_id = numeric_id(channel),
+        sent_at = sent_at(),
+        user_id = numeric_id(user),
+    )
+}
+
+pub fn notice(channel: &str, message_id: &str, text: &str) -> String {
+    format!("@msg-id={message_id} :tmi.twitch.tv NOTICE #{channel} :{text}")
+}
+
+/// The ROOMSTATE confirming a join.
+pub fn roomstate(channel: &str) -> String {
+    format!(
+        "@emote-only=0;followers-only=-1;r9k=0;room-id={room_id};slow=0;subs-only=0 \
+         :tmi.twitch.tv ROOMSTATE #{channel}",
+        room_id = numeric_id(channel),
+    )
+}
+
+/// The USERSTATE of a regular chatter, sent on joins and after each PRIVMSG.
+pub fn userstate(channel: &str, user: &str) -> String {
+    format!(
+        "@badge-info=;badges=;color=;display-name={user};emote-sets=0;mod=0;subscriber=0;\
+         user-type= :tmi.twitch.tv USERSTATE #{channel}"
+    )
+}
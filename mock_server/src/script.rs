@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+/// How a [`MockServer`](crate::MockServer) behaves beyond answering like
+/// Twitch. Channels are named without their `#`.
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    pub(crate) rejected_login: bool,
+    pub(crate) rejected_capabilities: Vec<String>,
+    pub(crate) suspended_channels: Vec<String>,
+    pub(crate) message_limit: Option<(usize, Duration)>,
+    pub(crate) welcome_lines: Vec<String>,
+    pub(crate) join_lines: Vec<(String, String)>,
+    pub(crate) dropped_joins: Vec<String>,
+    pub(crate) hung_up_joins: Vec<String>,
+    pub(crate) unanswered_joins: Vec<String>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers the login with Twitch's authentication failure NOTICE and
+    /// closes the connection.
+    pub fn with_rejected_login(mut self) -> Self {
+        self.rejected_login = true;
+        self
+    }
+
+    /// NAKs capability requests including `capability`, e.g. `"tags"`.
+    pub fn with_rejected_capability(mut self, capability: &str) -> Self {
+        self.rejected_capabilities.push(capability.into());
+        self
+    }
+
+    /// Refuses joins of `channel` with a `msg_channel_suspended` NOTICE.
+    pub fn with_suspended_channel(mut self, channel: &str) -> Self {
+        self.suspended_channels.push(channel.into());
+        self
+    }
+
+    /// Refuses PRIVMSGs beyond `limit` per `period` and connection with a
+    /// `msg_ratelimit` NOTICE, like Twitch does instead of dropping silently.
+    pub fn with_message_limit(mut self, limit: usize, period: Duration) -> Self {
+        self.message_limit = Some((limit, period));
+        self
+    }
+
+    /// Sends `line` on every connection right after the welcome.
+    pub fn with_welcome_line(mut self, line: &str) -> Self {
+        self.welcome_lines.push(line.into());
+        self
+    }
+
+    /// Sends `line` after each confirmed join of `channel`.
+    pub fn with_join_line(mut self, channel: &str, line: &str) -> Self {
+        self.join_lines.push((channel.into(), line.into()));
+        self
+    }
+
+    /// Closes the first connection joining `channel` right after confirming
+    /// the join.
+    pub fn with_dropped_join(mut self, channel: &str) -> Self {
+        self.dropped_joins.push(channel.into());
+        self
+    }
+
+    /// Leaves joins of `channel` unanswered, like Twitch when it is slow to
+    /// confirm them.
+    pub fn with_unanswered_join(mut self, channel: &str) -> Self {
+        self.unanswered_joins.push(channel.into());
+        self
+    }
+
+    /// Closes the first connection joining `channel` without answering the
+    /// join.
+    pub fn with_hung_up_join(mut self, channel: &str) -> Self {
+        self.hung_up_joins.push(channel.into());
+        self
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+use tokio::time::Instant;
+
+use crate::{messages, Shared};
+
+const CAPABILITY_PREFIX: &str = "twitch.tv/";
+
+/// State of one client connection and the replies to its lines.
+pub(crate) struct Session {
+    nickname: String,
+    /// When the PRIVMSGs inside the current rate limit window were sent.
+    sent: VecDeque<Instant>,
+}
+
+impl Session {
+    pub(crate) fn new() -> Self {
+        Self {
+            nickname: "*".into(),
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Lines answering `line` and whether to close the connection after them.
+    pub(crate) fn respond(&mut self, shared: &Shared, line: &str) -> (Vec<String>, bool) {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "NICK" if shared.script().rejected_login => (
+                vec![":tmi.twitch.tv NOTICE * :Login authentication failed".into()],
+                true,
+            ),
+            "NICK" => {
+                self.nickname = rest.into();
+                let mut replies = self.welcome();
+                replies.extend(shared.script().welcome_lines.iter().cloned());
+                (replies, false)
+            }
+            "CAP" => (self.capabilities(shared, rest).into_iter().collect(), false),
+            "JOIN" => {
+                let mut replies = vec![];
+                for channel in rest.split(',') {
+                    let channel = channel.trim_start_matches('#');
+                    if shared.hang_up_join(channel) {
+                        return (replies, true);
+                    }
+                    replies.extend(self.join(shared, channel));
+                    if shared.drop_join(channel) {
+                        return (replies, true);
+                    }
+                }
+                (replies, false)
+            }
+            "PART" => {
+                let nickname = &self.nickname;
+                let replies = rest
+                    .split(',')
+                    .map(|channel| {
+                        format!(":{nickname}!{nickname}@{nickname}.tmi.twitch.tv PART {channel}")
+                    })
+                    .collect();
+                (replies, false)
+            }
+            "PING" => (
+                vec![format!(":tmi.twitch.tv PONG tmi.twitch.tv {rest}")],
+                false,
+            ),
+            "PRIVMSG" => {
+                let channel = rest
+                    .split_once(' ')
+                    .map_or(rest, |(channel, _)| channel)
+                    .trim_start_matches('#');
+                (vec![self.privmsg(shared, channel)], false)
+            }
+            _ => (vec![], false),
+        }
+    }
+
+    fn welcome(&self) -> Vec<String> {
+        [
+            "001 {} :Welcome, GLHF!",
+            "002 {} :Your host is tmi.twitch.tv",
+            "003 {} :This server is rather new",
+            "004 {} :-",
+            "375 {} :-",
+            "372 {} :You are in a maze of twisty passages, all alike.",
+            "376 {} :>",
+        ]
+        .iter()
+        .map(|reply| format!(":tmi.twitch.tv {}", reply.replace("{}", &self.nickname)))
+        .collect()
+    }
+
+    fn capabilities(&self, shared: &Shared, request: &str) -> Option<String> {
+        let requested = request.strip_prefix("REQ :")?;
+        let rejected = requested.split_whitespace().any(|capability| {
+            let capability = capability
+                .strip_prefix(CAPABILITY_PREFIX)
+                .unwrap_or(capability);
+            shared
+                .script()
+                .rejected_capabilities
+                .iter()
+                .any(|rejected| rejected == capability)
+        });
+        let answer = if rejected { "NAK" } else { "ACK" };
+        Some(format!(":tmi.twitch.tv CAP * {answer} :{requested}"))
+    }
+
+    fn join(&self, shared: &Shared, channel: &str) -> Vec<String> {
+        let script = shared.script();
+        if script.unanswered_joins.iter().any(|name| name == channel) {
+            return vec![];
+        }
+        if script.suspended_channels.iter().any(|name| name == channel) {
+            return vec![messages::notice(
+                channel,
+                "msg_channel_suspended",
+                "This channel does not exist or has been suspended.",
+            )];
+        }
+        let nickname = &self.nickname;
+        let mut replies = vec![
+            format!(":{nickname}!{nickname}@{nickname}.tmi.twitch.tv JOIN #{channel}"),
+            format!(":{nickname}.tmi.twitch.tv 353 {nickname} = #{channel} :{nickname}"),
+            format!(":{nickname}.tmi.twitch.tv 366 {nickname} #{channel} :End of /NAMES list"),
+            messages::userstate(channel, nickname),
+            messages::roomstate(channel),
+        ];
+        replies.extend(
+            script
+                .join_lines
+                .iter()
+                .filter(|(name, _)| name == channel)
+                .map(|(_, line)| line.clone()),
+        );
+        replies
+    }
+
+    fn privmsg(&mut self, shared: &Shared, channel: &str) -> String {
+        if let Some((limit, period)) = shared.script().message_limit {
+            let now = Instant::now();
+            while self
+                .sent
+                .front()
+                .is_some_and(|sent| now.duration_since(*sent) >= period)
+            {
+                self.sent.pop_front();
+            }
+            if self.sent.len() >= limit {
+                return messages::notice(
+                    channel,
+                    "msg_ratelimit",
+                    "Your message was not sent because you are sending messages too quickly.",
+                );
+            }
+            self.sent.push_back(now);
+        }
+        messages::userstate(channel, &self.nickname)
+    }
+}
@@ -0,0 +1,26 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use parser::trirk_parser::TrirkParser;
+
+const PRIVMSG: &str = "@badge-info=subscriber/14;badges=moderator/1,subscriber/12,partner/1;client-nonce=6e5b9e8f4c1a4e0b9a1d2c3b4a596877;color=#1E90FF;display-name=StreamElements;emotes=25:0-4,12-16/1902:6-10;first-msg=0;flags=;id=3af8a524-f6c3-41b0-a54f-c254d6462928;mod=1;reply-parent-msg-id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;returning-chatter=0;room-id=72319043;subscriber=1;tmi-sent-ts=1700963394447;turbo=0;user-id=100135110;user-type=mod;vip=0 :streamelements!streamelements@streamelements.tmi.twitch.tv PRIVMSG #kingvenom :Kappa Keepo Kappa Lista de Musicas do Songrequest: https://nightbot.tv/t/kingvenom/song_requests\r\n";
+
+const USERNOTICE: &str = "@badge-info=subscriber/0;badges=subscriber/0,premium/1;color=;display-name=ronni;emotes=;flags=;id=db25007f-7a18-43eb-9379-80131e44d633;login=ronni;mod=0;msg-id=resub;msg-param-cumulative-months=6;msg-param-months=0;msg-param-multimonth-duration=0;msg-param-multimonth-tenure=0;msg-param-should-share-streak=1;msg-param-streak-months=2;msg-param-sub-plan-name=Prime;msg-param-sub-plan=Prime;msg-param-was-gifted=false;room-id=12345678;subscriber=1;system-msg=ronni\\shas\\ssubscribed\\sfor\\s6\\smonths!;tmi-sent-ts=1507246572675;user-id=87654321;user-type=;vip=0 :tmi.twitch.tv USERNOTICE #dallas :Great stream -- keep it up!\r\n";
+
+const ROOMSTATE: &str =
+    "@emote-only=0;followers-only=-1;r9k=0;room-id=12345678;slow=0;subs-only=0 :tmi.twitch.tv ROOMSTATE #dallas\r\n";
+
+fn bench_parse(c: &mut Criterion) {
+    let parser = TrirkParser::new();
+    let mut group = c.benchmark_group("parse");
+    for (name, line) in [
+        ("privmsg", PRIVMSG),
+        ("usernotice", USERNOTICE),
+        ("roomstate", ROOMSTATE),
+    ] {
+        group.throughput(Throughput::Bytes(line.len() as u64));
+        group.bench_function(name, |b| b.iter(|| parser.parse(black_box(line))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
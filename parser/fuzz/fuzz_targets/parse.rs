@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use parser::trirk_parser::TrirkParser;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = TrirkParser::new().parse_bytes(data);
+});
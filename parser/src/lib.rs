@@ -1,2 +1,3 @@
 pub mod trirk_parser;
+pub use trirk_parser::event::*;
 pub use trirk_parser::twitch::*;
@@ -2,16 +2,22 @@ use std::{error::Error, fmt::Display};
 
 #[derive(Debug, PartialEq)]
 pub struct UnparsableError {
+    kind: UnparsableErrorKind,
     message: String,
 }
 
 impl UnparsableError {
     #[inline(always)]
-    pub fn new<T: Into<String>>(message: T) -> Self {
+    pub fn new<T: Into<String>>(kind: UnparsableErrorKind, message: T) -> Self {
         Self {
+            kind,
             message: message.into(),
         }
     }
+
+    pub fn kind(&self) -> UnparsableErrorKind {
+        self.kind
+    }
 }
 
 impl Display for UnparsableError {
@@ -21,3 +27,19 @@ impl Display for UnparsableError {
 }
 
 impl Error for UnparsableError {}
+
+/// What made a line or a value unparsable.
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UnparsableErrorKind {
+    /// The line holds nothing but its line ending.
+    Empty,
+    /// The tags or the prefix are not followed by a command.
+    MissingCommand,
+    /// The command is not one Twitch sends and the line has no prefix.
+    UnknownCommand,
+    InvalidChannel,
+    InvalidColor,
+    /// The line is longer than [`super::line_buffer::MAX_LINE_LENGTH`].
+    LineTooLong,
+}
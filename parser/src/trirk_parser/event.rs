@@ -0,0 +1,426 @@
+use std::time::Duration;
+
+use super::twitch::{ChannelName, CommandType, Source, Tags, TwitchMessage};
+
+/// High level view of a [`TwitchMessage`], carrying only the fields that
+/// make sense for each kind of message.
+///
+/// Tags are `None` when the connection did not request the tags capability.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Event {
+    ChatMessage {
+        channel: ChannelName,
+        sender: Source,
+        text: String,
+        tags: Option<Tags>,
+    },
+    Join {
+        channel: ChannelName,
+        user: String,
+    },
+    Part {
+        channel: ChannelName,
+        user: String,
+    },
+    /// Also reported for timeouts seen without tags, as only the
+    /// `ban-duration` tag tells them apart.
+    Ban {
+        channel: ChannelName,
+        user: String,
+        tags: Option<Tags>,
+    },
+    Timeout {
+        channel: ChannelName,
+        user: String,
+        duration: Duration,
+        tags: Tags,
+    },
+    MessageDeleted {
+        channel: ChannelName,
+        login: String,
+        message_id: String,
+        text: String,
+    },
+    ChatCleared {
+        channel: ChannelName,
+    },
+    RoomStateChanged {
+        channel: ChannelName,
+        state: RoomState,
+    },
+    UserNotice {
+        channel: ChannelName,
+        kind: UserNoticeKind,
+        text: Option<String>,
+        tags: Option<Tags>,
+    },
+    Notice {
+        channel: Option<ChannelName>,
+        kind: NoticeKind,
+        text: String,
+    },
+    Whisper {
+        sender: Source,
+        text: String,
+        tags: Option<Tags>,
+    },
+    Ping {
+        server: Option<String>,
+    },
+    Reconnect,
+    /// Anything that does not fit the variants above, e.g. CAP or numerics.
+    Other(TwitchMessage),
+}
+
+/// Chat settings a ROOMSTATE carries. Twitch sends every setting on join
+/// and only the changed one afterwards, so each field is `None` when the
+/// update left the setting unchanged.
+#[derive(Default, PartialEq, Eq, Debug, Clone)]
+pub struct RoomState {
+    pub emote_only: Option<bool>,
+    /// `Some(None)` when followers-only mode is off, otherwise the minimum
+    /// follow age, zero when any follower may chat.
+    pub followers_only: Option<Option<Duration>>,
+    pub r9k: Option<bool>,
+    pub slow: Option<Duration>,
+    pub subs_only: Option<bool>,
+}
+
+impl From<&Tags> for RoomState {
+    fn from(tags: &Tags) -> Self {
+        Self {
+            emote_only: *tags.emote_only(),
+            followers_only: tags.followers_only().map(|minutes| {
+                u64::try_from(minutes)
+                    .ok()
+                    .map(|minutes| Duration::from_secs(minutes * 60))
+            }),
+            r9k: *tags.r9k(),
+            slow: tags.slow().map(|slow| Duration::from_secs(slow as u64)),
+            subs_only: *tags.subs_only(),
+        }
+    }
+}
+
+/// `msg-id` of a USERNOTICE.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum UserNoticeKind {
+    Sub,
+    Resub,
+    SubGift,
+    SubMysteryGift,
+    GiftPaidUpgrade,
+    AnonGiftPaidUpgrade,
+    RewardGift,
+    Raid,
+    Unraid,
+    Ritual,
+    BitsBadgeTier,
+    Announcement,
+    Unknown(String),
+}
+
+impl From<&str> for UserNoticeKind {
+    fn from(value: &str) -> Self {
+        match value {
+            "sub" => Self::Sub,
+            "resub" => Self::Resub,
+            "subgift" => Self::SubGift,
+            "submysterygift" => Self::SubMysteryGift,
+            "giftpaidupgrade" => Self::GiftPaidUpgrade,
+            "anongiftpaidupgrade" => Self::AnonGiftPaidUpgrade,
+            "rewardgift" => Self::RewardGift,
+            "raid" => Self::Raid,
+            "unraid" => Self::Unraid,
+            "ritual" => Self::Ritual,
+            "bitsbadgetier" => Self::BitsBadgeTier,
+            "announcement" => Self::Announcement,
+            unk => Self::Unknown(unk.into()),
+        }
+    }
+}
+
+/// `msg-id` of a NOTICE.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum NoticeKind {
+    ChannelSuspended,
+    Banned,
+    TimedOut,
+    RateLimited,
+    Duplicate,
+    SlowMode,
+    EmoteOnly,
+    FollowersOnly,
+    SubsOnly,
+    R9k,
+    DeleteMessageSuccess,
+    WhisperRestricted,
+    Unknown(String),
+}
+
+impl From<&str> for NoticeKind {
+    fn from(value: &str) -> Self {
+        match value {
+            "msg_channel_suspended" => Self::ChannelSuspended,
+            "msg_banned" => Self::Banned,
+            "msg_timedout" => Self::TimedOut,
+            "msg_ratelimit" => Self::RateLimited,
+            "msg_duplicate" => Self::Duplicate,
+            "msg_slowmode" => Self::SlowMode,
+            "msg_emoteonly" => Self::EmoteOnly,
+            "msg_followersonly" | "msg_followersonly_zero" | "msg_followersonly_followed" => {
+                Self::FollowersOnly
+            }
+            "msg_subsonly" => Self::SubsOnly,
+            "msg_r9k" => Self::R9k,
+            "delete_message_success" => Self::DeleteMessageSuccess,
+            "whisper_restricted" => Self::WhisperRestricted,
+            unk => Self::Unknown(unk.into()),
+        }
+    }
+}
+
+impl TwitchMessage {
+    /// Classifies the message into an [`Event`] by its command and parameters.
+    /// Messages missing the parts an event needs (e.g. a PRIVMSG without a
+    /// channel, or a CLEARMSG without the tags naming the message) become
+    /// [`Event::Other`].
+    pub fn into_event(self) -> Event {
+        let channel = self.command.channel().clone();
+        let message_id = self.tags.as_ref().map(|tags| tags.message_id().clone());
+        match (self.command.command(), channel) {
+            (CommandType::Ping, _) => Event::Ping {
+                server: self.parameters,
+            },
+            (CommandType::Reconnect, _) => Event::Reconnect,
+            (CommandType::Notice, channel) => Event::Notice {
+                channel,
+                kind: NoticeKind::from(message_id.unwrap_or_default().as_str()),
+                text: self.parameters.unwrap_or_default(),
+            },
+            (CommandType::Whisper, _) if self.source.is_some() => Event::Whisper {
+                sender: self.source.unwrap(),
+                text: self.parameters.unwrap_or_default(),
+                tags: self.tags,
+            },
+            (CommandType::Join, Some(channel)) if self.source.is_some() => Event::Join {
+                channel,
+                user: self.source.unwrap().nick(),
+            },
+            (CommandType::Part(_), Some(channel)) if self.source.is_some() => Event::Part {
+                channel,
+                user: self.source.unwrap().nick(),
+            },
+            (CommandType::PrivMSG, Some(channel)) if self.source.is_some() => Event::ChatMessage {
+                channel,
+                sender: self.source.unwrap(),
+                text: self.parameters.unwrap_or_default(),
+                tags: self.tags,
+            },
+            (CommandType::ClearChat, Some(channel)) => match (self.parameters, self.tags) {
+                (None, _) => Event::ChatCleared { channel },
+                (Some(user), Some(tags)) if *tags.ban_duration() > 0 => Event::Timeout {
+                    channel,
+                    user,
+                    duration: Duration::from_secs(*tags.ban_duration() as u64),
+                    tags,
+                },
+                (Some(user), tags) => Event::Ban {
+                    channel,
+                    user,
+                    tags,
+                },
+            },
+            (CommandType::ClearMessage, Some(channel)) if self.tags.is_some() => {
+                let tags = self.tags.unwrap();
+                Event::MessageDeleted {
+                    channel,
+                    login: tags.login().to_string(),
+                    message_id: tags.target_message_id().to_string(),
+                    text: self.parameters.unwrap_or_default(),
+                }
+            }
+            (CommandType::RoomState, Some(channel)) if self.tags.is_some() => {
+                Event::RoomStateChanged {
+                    channel,
+                    state: RoomState::from(self.tags.as_ref().unwrap()),
+                }
+            }
+            (CommandType::UserNotice, Some(channel)) => Event::UserNotice {
+                channel,
+                kind: UserNoticeKind::from(message_id.unwrap_or_default().as_str()),
+                text: self.parameters,
+                tags: self.tags,
+            },
+            _ => Event::Other(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Event, NoticeKind, RoomState, UserNoticeKind};
+    use crate::trirk_parser::{twitch::ChannelName, TrirkParser};
+
+    fn event(msg: &str) -> Event {
+        TrirkParser::new().parse(msg).unwrap().into_event()
+    }
+
+    fn dallas() -> ChannelName {
+        ChannelName::new("dallas").unwrap()
+    }
+
+    #[test]
+    fn should_classify_chat_message() {
+        let Event::ChatMessage {
+            channel,
+            sender,
+            text,
+            tags,
+        } = event("@display-name=Ronni;mod=1 :ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #dallas :hi")
+        else {
+            panic!("expected chat message");
+        };
+        assert_eq!(dallas(), channel);
+        assert_eq!("ronni", sender.nick());
+        assert_eq!("hi", text);
+        assert!(tags.unwrap().r#mod());
+    }
+
+    #[test]
+    fn should_classify_untagged_chat_message() {
+        assert!(matches!(
+            event(":ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #dallas :hi"),
+            Event::ChatMessage { sender, text, tags: None, .. } if sender.nick() == "ronni" && text == "hi"
+        ));
+    }
+
+    #[test]
+    fn should_classify_clearchat() {
+        assert!(matches!(
+            event("@ban-duration=350;target-user-id=87654321 :tmi.twitch.tv CLEARCHAT #dallas :ronni"),
+            Event::Timeout { user, duration, .. } if user == "ronni" && duration == Duration::from_secs(350)
+        ));
+        assert!(matches!(
+            event("@target-user-id=87654321 :tmi.twitch.tv CLEARCHAT #dallas :ronni"),
+            Event::Ban { user, .. } if user == "ronni"
+        ));
+        assert_eq!(
+            Event::ChatCleared { channel: dallas() },
+            event("@room-id=12345678 :tmi.twitch.tv CLEARCHAT #dallas")
+        );
+        assert_eq!(
+            Event::Ban {
+                channel: dallas(),
+                user: "ronni".into(),
+                tags: None,
+            },
+            event(":tmi.twitch.tv CLEARCHAT #dallas :ronni")
+        );
+        assert_eq!(
+            Event::ChatCleared { channel: dallas() },
+            event(":tmi.twitch.tv CLEARCHAT #dallas")
+        );
+    }
+
+    #[test]
+    fn should_classify_message_deleted() {
+        assert_eq!(
+            Event::MessageDeleted {
+                channel: dallas(),
+                login: "ronni".into(),
+                message_id: "abc-123-def".into(),
+                text: "HeyGuys".into(),
+            },
+            event("@login=ronni;room-id=;target-msg-id=abc-123-def :tmi.twitch.tv CLEARMSG #dallas :HeyGuys")
+        );
+    }
+
+    #[test]
+    fn should_classify_membership_and_state() {
+        assert_eq!(
+            Event::Join {
+                channel: dallas(),
+                user: "ronni".into()
+            },
+            event(":ronni!ronni@ronni.tmi.twitch.tv JOIN #dallas")
+        );
+        assert_eq!(
+            Event::Part {
+                channel: dallas(),
+                user: "ronni".into()
+            },
+            event(":ronni!ronni@ronni.tmi.twitch.tv PART #dallas")
+        );
+        assert_eq!(
+            Event::RoomStateChanged {
+                channel: dallas(),
+                state: RoomState {
+                    slow: Some(Duration::from_secs(10)),
+                    ..Default::default()
+                }
+            },
+            event("@slow=10 :tmi.twitch.tv ROOMSTATE #dallas")
+        );
+        assert_eq!(
+            Event::RoomStateChanged {
+                channel: dallas(),
+                state: RoomState {
+                    emote_only: Some(true),
+                    ..Default::default()
+                }
+            },
+            event("@emote-only=1 :tmi.twitch.tv ROOMSTATE #dallas")
+        );
+    }
+
+    #[test]
+    fn should_tell_followers_only_off_from_no_minimum() {
+        let followers_only = |value| match event(&format!(
+            "@followers-only={value} :tmi.twitch.tv ROOMSTATE #dallas"
+        )) {
+            Event::RoomStateChanged { state, .. } => state.followers_only,
+            other => panic!("not a room state: {other:?}"),
+        };
+        assert_eq!(Some(None), followers_only("-1"));
+        assert_eq!(Some(Some(Duration::ZERO)), followers_only("0"));
+        assert_eq!(Some(Some(Duration::from_secs(600))), followers_only("10"));
+    }
+
+    #[test]
+    fn should_classify_notices() {
+        assert!(matches!(
+            event("@msg-id=resub;login=ronni :tmi.twitch.tv USERNOTICE #dallas :Great stream"),
+            Event::UserNotice { kind: UserNoticeKind::Resub, text: Some(text), .. } if text == "Great stream"
+        ));
+        assert_eq!(
+            Event::Notice {
+                channel: Some(dallas()),
+                kind: NoticeKind::ChannelSuspended,
+                text: "This channel has been suspended.".into()
+            },
+            event("@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #dallas :This channel has been suspended.")
+        );
+    }
+
+    #[test]
+    fn should_classify_server_commands() {
+        assert_eq!(
+            Event::Ping {
+                server: Some("tmi.twitch.tv".into())
+            },
+            event("PING :tmi.twitch.tv")
+        );
+        assert_eq!(Event::Reconnect, event(":tmi.twitch.tv RECONNECT"));
+        assert!(matches!(
+            event(":petsgomoo!petsgomoo@petsgomoo.tmi.twitch.tv WHISPER foo :hello"),
+            Event::Whisper { sender, text, .. } if sender.nick() == "petsgomoo" && text == "hello"
+        ));
+        assert!(matches!(
+            event(":tmi.twitch.tv 001 foo :Welcome, GLHF!"),
+            Event::Other(_)
+        ));
+    }
+}
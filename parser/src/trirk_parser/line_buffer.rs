@@ -0,0 +1,137 @@
+use super::error::{UnparsableError, UnparsableErrorKind};
+
+/// Longest line kept, line ending included: IRCv3 allows 8191 bytes of tags
+/// on top of the 512 bytes of a plain IRC line.
+pub const MAX_LINE_LENGTH: usize = 8191 + 512;
+
+/// Accumulates raw socket reads and hands them back one IRC line at a time.
+///
+/// Bytes are kept until a `\n` arrives, so a multibyte character split
+/// across two reads is only decoded once both halves are present. Lines
+/// longer than [`MAX_LINE_LENGTH`] are dropped instead of buffered.
+#[derive(Default, Debug)]
+pub struct LineBuffer {
+    buffer: Vec<u8>,
+    /// Bytes of the unfinished line at the end of `buffer`.
+    pending: usize,
+    /// Whether the rest of an oversized line is being skipped.
+    discarding: bool,
+}
+
+impl LineBuffer {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `bytes`, failing with [`UnparsableErrorKind::LineTooLong`]
+    /// when they made a line go over [`MAX_LINE_LENGTH`]. That line is
+    /// dropped up to its `\n`; every other line is kept.
+    pub fn extend(&mut self, mut bytes: &[u8]) -> Result<(), UnparsableError> {
+        let mut dropped = 0;
+        while !bytes.is_empty() {
+            let end = bytes.iter().position(|byte| *byte == b'\n');
+            let (chunk, rest) = bytes.split_at(end.map_or(bytes.len(), |idx| idx + 1));
+            bytes = rest;
+            let ends_line = end.is_some();
+            if self.discarding {
+                self.discarding = !ends_line;
+                continue;
+            }
+            if self.pending + chunk.len() > MAX_LINE_LENGTH {
+                self.buffer.truncate(self.buffer.len() - self.pending);
+                self.pending = 0;
+                self.discarding = !ends_line;
+                dropped += 1;
+                continue;
+            }
+            self.buffer.extend_from_slice(chunk);
+            self.pending = if ends_line {
+                0
+            } else {
+                self.pending + chunk.len()
+            };
+        }
+        if dropped > 0 {
+            return Err(UnparsableError::new(
+                UnparsableErrorKind::LineTooLong,
+                format!("dropped {dropped} line(s) longer than {MAX_LINE_LENGTH} bytes"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Removes the next complete line, without its `\r\n`, from the buffer.
+    pub fn next_line(&mut self) -> Option<Vec<u8>> {
+        let newline_idx = self.buffer.iter().position(|byte| *byte == b'\n')?;
+        let mut line: Vec<u8> = self.buffer.drain(..=newline_idx).collect();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Some(line)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LineBuffer, MAX_LINE_LENGTH};
+    use crate::trirk_parser::error::UnparsableErrorKind;
+
+    #[test]
+    fn should_split_lines_across_reads() {
+        let mut lines = LineBuffer::new();
+        lines
+            .extend(b"PING :tmi.twitch.tv\r\n:foo!foo@foo.tmi")
+            .unwrap();
+        assert_eq!(Some(b"PING :tmi.twitch.tv".to_vec()), lines.next_line());
+        assert_eq!(None, lines.next_line());
+        lines.extend(b".twitch.tv JOIN #bar\r\n").unwrap();
+        assert_eq!(
+            Some(b":foo!foo@foo.tmi.twitch.tv JOIN #bar".to_vec()),
+            lines.next_line()
+        );
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn should_reassemble_multibyte_characters_split_across_reads() {
+        let line = "PRIVMSG #bar :você\r\n".as_bytes();
+        let split_idx = line.iter().position(|byte| *byte == 0xC3).unwrap() + 1;
+        let mut lines = LineBuffer::new();
+        lines.extend(&line[..split_idx]).unwrap();
+        assert_eq!(None, lines.next_line());
+        lines.extend(&line[split_idx..]).unwrap();
+        let line = lines.next_line().unwrap();
+        assert_eq!("PRIVMSG #bar :você", String::from_utf8(line).unwrap());
+    }
+
+    #[test]
+    fn should_drop_lines_over_the_limit() {
+        let mut lines = LineBuffer::new();
+        let oversized = vec![b'a'; MAX_LINE_LENGTH];
+        lines.extend(b"PING :tmi.twitch.tv\r\n@").unwrap();
+        let err = lines.extend(&oversized).unwrap_err();
+        assert_eq!(UnparsableErrorKind::LineTooLong, err.kind());
+        lines.extend(&oversized).unwrap();
+        lines.extend(b"aaa\r\nPONG :tmi.twitch.tv\r\n").unwrap();
+        assert_eq!(Some(b"PING :tmi.twitch.tv".to_vec()), lines.next_line());
+        assert_eq!(Some(b"PONG :tmi.twitch.tv".to_vec()), lines.next_line());
+        assert!(lines.is_empty());
+
+        let mut longest = vec![b'a'; MAX_LINE_LENGTH - 2];
+        longest.extend_from_slice(b"\r\n");
+        lines.extend(&longest).unwrap();
+        assert_eq!(
+            Some(MAX_LINE_LENGTH - 2),
+            lines.next_line().map(|line| line.len())
+        );
+        longest.insert(0, b'a');
+        assert!(lines.extend(&longest).is_err());
+        assert_eq!(None, lines.next_line());
+    }
+}
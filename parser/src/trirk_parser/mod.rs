@@ -1,189 +1,202 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, fmt::Debug, sync::Arc};
 
 use self::{
-    error::UnparsableError,
-    twitch::{Badge, Command, CommandType, Emote, Source, Tags, TwitchMessage},
+    error::{UnparsableError, UnparsableErrorKind},
+    tag_decoder::{TagDecoder, TagValue},
+    twitch::{
+        Badge, ChannelName, Color, Command, CommandType, Emote, Source, TagStr, Tags, Timestamp,
+        TwitchMessage, UserType,
+    },
 };
 
 pub mod error;
+pub mod event;
+pub mod line_buffer;
+pub mod tag_decoder;
 pub mod twitch;
 
 #[non_exhaustive]
-#[derive(Default)]
-pub struct TrirkParser;
+#[derive(Default, Clone)]
+pub struct TrirkParser {
+    decoders: Vec<(String, TagDecoder)>,
+}
 
 impl TrirkParser {
     #[inline(always)]
     pub const fn new() -> Self {
-        Self
+        Self {
+            decoders: Vec::new(),
+        }
+    }
+
+    /// Registers a decoder for the tag `key`. Its output can be read back with
+    /// [`Tags::get`]; the raw value is still kept where it would normally go.
+    pub fn with_tag_decoder<K, T, F>(mut self, key: K, decoder: F) -> Self
+    where
+        K: Into<String>,
+        T: Clone + PartialEq + Debug + Send + Sync + 'static,
+        F: Fn(&str) -> Option<T> + Send + Sync + 'static,
+    {
+        let decoder: TagDecoder =
+            Arc::new(move |value| decoder(value).map(|value| Box::new(value) as Box<dyn TagValue>));
+        self.decoders.push((key.into(), decoder));
+        self
     }
 
-    pub fn parse<T: Into<String>>(&self, msg: T) -> Result<TwitchMessage, UnparsableError> {
-        let msg: String = msg.into();
+    /// Parses a single IRC line, walking it once from left to right:
+    /// `[@tags] [:source] COMMAND [middle params] [:trailing]`.
+    pub fn parse<T: AsRef<str>>(&self, msg: T) -> Result<TwitchMessage, UnparsableError> {
+        let msg = msg.as_ref().trim_end_matches(['\r', '\n']);
         if msg.is_empty() {
-            return Err(UnparsableError::new("empty irc message"));
+            return Err(UnparsableError::new(
+                UnparsableErrorKind::Empty,
+                "empty irc message",
+            ));
         }
-        let mut idx = 0;
-        let tags: Option<Tags> = if msg.starts_with('@') {
-            let Some(space_idx) = msg.find(' ') else {
-                Err(UnparsableError::new("message does not contains any space"))?
-            };
-            idx = space_idx + 1;
-            let sub_str = &msg[0..space_idx];
-            Some(self.parse_tags(sub_str))
-        } else {
-            None
+
+        let (tags, rest) = match msg.strip_prefix('@') {
+            Some(tagged) => {
+                let Some((raw_tags, rest)) = tagged.split_once(' ') else {
+                    Err(UnparsableError::new(
+                        UnparsableErrorKind::MissingCommand,
+                        "message does not contains any space",
+                    ))?
+                };
+                (Some(self.parse_tags(raw_tags)), rest)
+            }
+            None => (None, msg),
         };
 
-        //FIX: parse PING
-        match (msg.get(idx..idx + 1), &msg[..]) {
-            (Some(":"), _) => {
-                idx += 1;
-                let sub_msg = &msg[idx..];
-                let Some(space_idx) = sub_msg.find(' ') else {
-                    Err(UnparsableError::new("message does not contains any space"))?
+        let rest = rest.trim_start_matches(' ');
+        let (source, rest) = match rest.strip_prefix(':') {
+            Some(prefixed) => {
+                let Some((raw_source, rest)) = prefixed.split_once(' ') else {
+                    Err(UnparsableError::new(
+                        UnparsableErrorKind::MissingCommand,
+                        "message does not contains any space",
+                    ))?
                 };
-                let source: Source = self.parse_source(&sub_msg[..space_idx]);
-                idx += space_idx + 1;
-                let (command, add_idx) = self.parse_command(&msg[idx..], &source);
-                idx += add_idx + 1;
-                let parameter = self.parse_parameter(&msg[idx..]);
-                Ok(TwitchMessage::new(parameter, command, Some(source), tags))
+                (Some(self.parse_source(raw_source)), rest)
             }
-            (_, msg) if msg.contains("PING") => Ok(TwitchMessage::new::<&str>(
-                None,
-                Command::new(CommandType::Ping, ""),
-                None,
-                tags,
-            )),
-            (_, msg) if msg.contains("PART") => self.parse_part(msg),
-            (_, msg) if msg.contains("JOIN") => self.parse_join(msg),
-            (current_char, msg) => Err(UnparsableError::new(format!(
-                "ERROR: could not parse message '{msg}' {complement}",
-                complement = current_char
-                    .map_or("".into(), |current| format!("with start char '{current}'"))
-            ))),
-        }
+            // membership lines are sometimes seen without the leading ':'
+            None => match rest.split_once(' ') {
+                Some((raw_source, rest)) if raw_source.contains('!') => {
+                    (Some(self.parse_source(raw_source)), rest)
+                }
+                _ => (None, rest),
+            },
+        };
+
+        let (raw_command, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        let (middle, trailing) = match rest.strip_prefix(':') {
+            Some(trailing) => ("", Some(trailing)),
+            None => match rest.split_once(" :") {
+                Some((middle, trailing)) => (middle, Some(trailing)),
+                None => (rest, None),
+            },
+        };
+        let command_type = match CommandType::from(raw_command) {
+            CommandType::Part(_) => {
+                CommandType::Part(source.iter().map(|source| source.nick()).collect())
+            }
+            // `CAP * ACK`, the subcommand follows the client id
+            CommandType::Cap(_) => {
+                CommandType::Cap(middle.split(' ').nth(1).unwrap_or_default().into())
+            }
+            CommandType::Unknown(_) if source.is_none() => {
+                return Err(UnparsableError::new(
+                    UnparsableErrorKind::UnknownCommand,
+                    format!("ERROR: could not parse message '{msg}'"),
+                ))
+            }
+            command_type => command_type,
+        };
+
+        let channel = middle
+            .split(' ')
+            .find(|param| param.starts_with('#'))
+            .and_then(|channel| ChannelName::new(channel).ok());
+
+        Ok(TwitchMessage::new(
+            trailing,
+            Command::new(command_type, channel),
+            source,
+            tags,
+        ))
+    }
+
+    /// Parses a single line of raw bytes. Invalid UTF-8 is replaced with
+    /// `U+FFFD` so a bad byte only garbles the line it appears in.
+    pub fn parse_bytes(&self, line: &[u8]) -> Result<TwitchMessage, UnparsableError> {
+        self.parse(String::from_utf8_lossy(line))
     }
 
     fn parse_tags(&self, input: &str) -> Tags {
-        let splited = input.split(';');
-        let mut tags = Tags::builder();
-        let mut extra_tags = HashMap::new();
-        for value in splited {
-            let mut key_value = value.split('=');
-            let Some(key) = key_value.next() else {
+        let mut tags = Tags::default();
+        let section: Arc<str> = input.into();
+        let mut pair_start = 0;
+        for pair in input.split(';') {
+            let value_start = pair_start;
+            pair_start += pair.len() + 1;
+            let Some((key, raw)) = pair.split_once('=') else {
                 continue;
             };
-            let Some(value) = key_value.next() else {
-                continue;
+            let value_start = value_start + key.len() + 1;
+            let unescaped = unescape_tag_value(raw);
+            let value = unescaped.as_ref();
+            let shared = || match &unescaped {
+                Cow::Borrowed(_) => TagStr::shared(&section, value_start..value_start + raw.len()),
+                Cow::Owned(value) => TagStr::from(value.as_str()),
             };
-            match key {
-                "@badges" | "badges" => {
-                    let badge: Badge = self.parse_badges(value);
-                    tags.badges(badge);
-                }
-                "color" => {
-                    tags.color(value);
-                }
-                "display-name" => {
-                    tags.display_name(value);
-                }
-                "emote-only" => {
-                    tags.emote_only(value == "1");
-                }
-                "emotes" => {
-                    let emotes: Vec<Emote> = self.parse_emotes(value);
-                    tags.emotes(emotes);
-                }
-                "id" => {
-                    tags.id(value);
-                }
-                "mod" => {
-                    tags.r#mod(value == "1");
-                }
-                "room-id" => {
-                    tags.room_id(value);
-                }
-                "subscriber" => {
-                    tags.subscriber(value == "1");
-                }
-                "turbo" => {
-                    tags.turbo(value == "1");
+            for (_, decoder) in self.decoders.iter().filter(|(k, _)| k == key) {
+                if let Some(decoded) = decoder(value) {
+                    tags.decoded.insert(key, decoded);
                 }
+            }
+            match key {
+                "badges" => tags.badges = self.parse_badges(value),
+                "color" => tags.color = value.parse::<Color>().ok(),
+                "display-name" => tags.display_name = shared(),
+                "emote-only" => tags.emote_only = Some(value == "1"),
+                "emotes" => tags.emotes = self.parse_emotes(value),
+                "id" => tags.id = shared(),
+                "mod" => tags.r#mod = value == "1",
+                "room-id" => tags.room_id = shared(),
+                "subscriber" => tags.subscriber = value == "1",
+                "turbo" => tags.turbo = value == "1",
                 "tmi-sent-ts" => {
-                    let Ok(value) = value.parse::<usize>() else {
-                        continue;
-                    };
-                    tags.tmi_sent_ts(value);
-                }
-                "user-id" => {
-                    tags.user_id(value);
-                }
-                "user-type" => {
-                    tags.user_type(value);
-                }
-                "vip" => {
-                    tags.vip(value == "1");
-                }
-                "reply-parent-msg-id" => {
-                    tags.reply_parent_msg_id(value);
-                }
-                "@msg-id" => {
-                    tags.message_id(value);
-                }
-                "target-user-id" => {
-                    tags.target_user_id(value);
-                }
-                "@room-id" => {
-                    tags.room_id(value);
-                }
-                "@ban-duration" => {
-                    tags.ban_duration(value.parse::<usize>().unwrap_or(0));
-                }
-                "@login" => {
-                    tags.login(value);
-                }
-                "target-msg-id" => {
-                    tags.target_message_id(value);
-                }
-                "emote-sets" => {
-                    let emote_sets = self.parse_emote_sets(value);
-                    tags.emote_sets(emote_sets);
-                }
-                "@emote-only" => {
-                    tags.emote_only(value == "1");
-                }
-                "followers-only" => {
-                    tags.followers_only(value == "1");
-                }
-                "r9k" => {
-                    tags.r9k(value == "1");
-                }
-                "slow" | "@slow" => {
-                    tags.slow(value.parse::<usize>().unwrap_or(0));
-                }
-                "subs-only" => {
-                    tags.subs_only(value == "1");
+                    tags.tmi_sent_ts = value.parse::<u64>().ok().and_then(Timestamp::from_millis)
                 }
+                "user-id" => tags.user_id = shared(),
+                "user-type" => tags.user_type = UserType::from(value),
+                "vip" => tags.vip = value == "1",
+                "reply-parent-msg-id" => tags.reply_parent_msg_id = shared(),
+                "msg-id" => tags.message_id = shared(),
+                "target-user-id" => tags.target_user_id = shared(),
+                "ban-duration" => tags.ban_duration = value.parse::<usize>().unwrap_or(0),
+                "login" => tags.login = shared(),
+                "target-msg-id" => tags.target_message_id = shared(),
+                "emote-sets" => tags.emote_sets = self.parse_emote_sets(value),
+                "followers-only" => tags.followers_only = value.parse::<isize>().ok(),
+                "r9k" => tags.r9k = Some(value == "1"),
+                "slow" => tags.slow = value.parse::<usize>().ok(),
+                "subs-only" => tags.subs_only = Some(value == "1"),
+                "source-room-id" => tags.source_room_id = shared(),
+                "source-id" => tags.source_id = shared(),
+                "source-badges" => tags.source_badges = self.parse_badges(value),
+                "source-only" => tags.source_only = value == "1",
                 unk => {
-                    extra_tags.insert(unk.into(), value.into());
+                    tags.extra_tags.insert(unk.into(), value.into());
                 }
             }
         }
-        tags.extra_tags(extra_tags);
-        tags.build().unwrap()
+        tags
     }
 
     fn parse_badges(&self, value: &str) -> Badge {
-        let badge_pair = value.split(',');
         let mut badge = Badge::default();
-        for badge_key_value in badge_pair {
-            let mut key_value = badge_key_value.split('/');
-            let Some(key) = key_value.next() else {
-                continue;
-            };
-            let Some(value) = key_value.next() else {
+        for badge_key_value in value.split(',') {
+            let Some((key, value)) = badge_key_value.split_once('/') else {
                 continue;
             };
             match key {
@@ -200,109 +213,83 @@ impl TrirkParser {
         badge
     }
 
+    /// Emotes come as `code:start-end,start-end/code:start-end`.
     fn parse_emotes(&self, value: &str) -> Vec<Emote> {
-        let emotes_pair = value.split(',');
         let mut emotes: Vec<Emote> = Vec::new();
-        for emote_key_value in emotes_pair {
-            let mut code_value = emote_key_value.split(':');
-            let Some(code) = code_value.next() else {
-                continue;
-            };
-            let Some(position) = code_value.next() else {
+        for emote in value.split('/') {
+            let Some((code, positions)) = emote.split_once(':') else {
                 continue;
             };
-            let mut start_end = position.split('-');
-            let Some(start) = start_end.next() else {
-                continue;
-            };
-            let Some(end) = start_end.next() else {
-                continue;
-            };
-            let Ok(start) = start.parse::<usize>() else {
-                continue;
-            };
-            let Ok(end) = end.parse::<usize>() else {
-                continue;
-            };
-            let emote = Emote::new(code, start, end);
-            emotes.push(emote);
+            for position in positions.split(',') {
+                let Some((start, end)) = position.split_once('-') else {
+                    continue;
+                };
+                let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) else {
+                    continue;
+                };
+                emotes.push(Emote::new(code, start, end));
+            }
         }
         emotes
     }
 
     fn parse_source(&self, value: &str) -> Source {
-        let bang_idx = value.find('!');
-        let at_idx = value.find('@');
-        match (bang_idx, at_idx) {
-            (Some(bang_idx), Some(at_idx)) => {
-                let nick = &value[0..bang_idx];
-                let host = &value[at_idx + 1..];
-                Source::new(nick, host)
-            }
+        match (value.split_once('!'), value.split_once('@')) {
+            (Some((nick, _)), Some((_, host))) => Source::new(nick, host),
             _ => Source::new("", value),
         }
     }
 
-    fn parse_command(&self, value: &str, source: &Source) -> (Command, usize) {
-        let Some(space_idx) = value.find(' ') else {
-            return (Command::new(CommandType::from(value), source.nick()), 0);
-        };
-        let value = &value[..space_idx];
-        let command = Command::new(CommandType::from(value), source.nick());
-        (command, space_idx)
-    }
-
-    fn parse_parameter(&self, value: &str) -> Option<String> {
-        let idx = value.find(':')?;
-        Some(value[idx + 1..].into())
-    }
-
     fn parse_emote_sets(&self, value: &str) -> Vec<usize> {
         value
             .split(',')
             .map(|v| v.parse::<usize>().unwrap_or(0))
             .collect()
     }
+}
 
-    fn parse_join(&self, msg: &str) -> Result<TwitchMessage, UnparsableError> {
-        let Some(nick) = msg.split('!').next() else {
-            Err(UnparsableError::new(format!(
-                "ERROR: could not parse '{}', not have '!'",
-                msg
-            )))?
-        };
-        let mut idx = (nick.len() * 2) + 2;
-        let sub_msg = &msg[idx..];
-
-        let Some(host) = sub_msg.split(' ').next() else {
-            Err(UnparsableError::new(format!(
-                "ERROR: could not parse '{}', not have host",
-                msg
-            )))?
-        };
-        idx += host.len() + 7;
-        let channel = &msg[idx..];
-        Ok(TwitchMessage::new::<String>(
-            None,
-            Command::new(CommandType::Join, channel),
-            Some(Source::new(nick.replace(':', ""), host.into())),
-            None,
-        ))
+/// Undoes the IRCv3 tag value escaping: `\:` is `;`, `\s` a space, `\\` a
+/// backslash and `\r`/`\n` CR/LF. Any other escaped character stands for
+/// itself and a trailing lone backslash is dropped.
+fn unescape_tag_value(value: &str) -> Cow<'_, str> {
+    if !value.contains('\\') {
+        return Cow::Borrowed(value);
     }
-
-    fn parse_part(&self, _msg: &str) -> Result<TwitchMessage, UnparsableError> {
-        Err(UnparsableError::new("PART: not implemented yet"))
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
     }
+    Cow::Owned(unescaped)
 }
 
 #[cfg(test)]
 mod test {
+    use std::{
+        collections::HashMap,
+        time::{Duration, UNIX_EPOCH},
+    };
 
     use super::{
-        twitch::{Badge, Command, CommandType, Emote, Source},
+        twitch::{Badge, ChannelName, Color, Command, CommandType, Emote, Source, UserType},
         *,
     };
 
+    fn channel(name: &str) -> Option<ChannelName> {
+        Some(ChannelName::new(name).unwrap())
+    }
+
     #[test]
     //FIX: mult part messages
     fn should_parse_part() {
@@ -311,7 +298,7 @@ mod test {
         let twitch_message = parser.parse(msg);
 
         let source = Source::new("kyoqz", "kyoqz.tmi.twitch.tv");
-        let command = Command::new(CommandType::Part(vec!["kyoqz".into()]), "kyoqz");
+        let command = Command::new(CommandType::Part(vec!["kyoqz".into()]), channel("evazord"));
 
         let expected_message = TwitchMessage::new::<String>(None, command, Some(source), None);
 
@@ -325,7 +312,7 @@ mod test {
         let twitch_message = parser.parse(msg);
 
         let source = Source::new("renildson", "renildson.tmi.twitch.tv");
-        let command = Command::new(CommandType::Join, "evazord");
+        let command = Command::new(CommandType::Join, channel("evazord"));
 
         let expected_message = TwitchMessage::new::<String>(None, command, Some(source), None);
         assert_eq!(Ok(expected_message), twitch_message);
@@ -337,34 +324,34 @@ mod test {
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
         let source = Source::new("streamelements", "streamelements.tmi.twitch.tv");
-        let command = Command::new(CommandType::PrivMSG, "streamelements");
+        let command = Command::new(CommandType::PrivMSG, channel("kingvenom"));
         let mut badges = Badge::default();
         badges.set_moderator("1".into());
         let mut extra_tags = HashMap::new();
         extra_tags.insert("client-nonce".into(), "01HG4N38VEHHKQWBHXDNKEFN33".into());
         extra_tags.insert("returning-chatter".into(), "0".into());
-        extra_tags.insert("@badge-info".into(), "".into());
+        extra_tags.insert("badge-info".into(), "".into());
         extra_tags.insert("first-msg".into(), "0".into());
         extra_tags.insert("flags".into(), "".into());
         let tags = Tags::builder()
             .badges(badges)
-            .color("#5B99FF")
+            .color(Color::new(0x5B, 0x99, 0xFF))
             .display_name("StreamElements")
-            .emote_only(false)
             .id("3af8a524-f6c3-41b0-a54f-c254d6462928")
             .r#mod(true)
             .room_id("72319043")
             .subscriber(false)
             .turbo(false)
-            .tmi_sent_ts(1700963394447usize)
+            .tmi_sent_ts(Timestamp::from_millis(1700963394447))
             .user_id("100135110")
-            .user_type("mod")
+            .user_type(UserType::Mod)
             .vip(false)
             .reply_parent_msg_id("")
             .extra_tags(extra_tags)
             .build()
             .unwrap();
-        let parameters = "Lista de Musicas do Songrequest: https://nightbot.tv/t/kingvenom/song_requests";
+        let parameters =
+            "Lista de Musicas do Songrequest: https://nightbot.tv/t/kingvenom/song_requests";
         let expected_message =
             TwitchMessage::new(Some(parameters), command, Some(source), Some(tags));
         assert_eq!(Ok(expected_message), twitch_message);
@@ -376,7 +363,7 @@ mod test {
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
         let source = Source::new("petsgomoo", "petsgomoo.tmi.twitch.tv");
-        let command = Command::new(CommandType::PrivMSG, "petsgomoo");
+        let command = Command::new(CommandType::PrivMSG, channel("petsgomoo"));
         let mut badges = Badge::default();
         badges.set_staff("1".into());
         badges.set_broadcaster("1".into());
@@ -385,18 +372,18 @@ mod test {
         extra_tags.insert("flags".into(), "0-7:A.6/P.6,25-36:A.1/I.2".into());
         let tags = Tags::builder()
             .badges(badges)
-            .color("#FF0000")
+            .color(Color::new(0xFF, 0x00, 0x00))
             .display_name("PetsgomOO")
-            .emote_only(true)
+            .emote_only(Some(true))
             .emotes(vec![Emote::new("33", 0, 7)])
             .id("c285c9ed-8b1b-4702-ae1c-c64d76cc74ef")
             .r#mod(false)
             .room_id("81046256")
             .subscriber(false)
             .turbo(false)
-            .tmi_sent_ts(1550868292494usize)
+            .tmi_sent_ts(Timestamp::from_millis(1550868292494))
             .user_id("81046256")
-            .user_type("staff")
+            .user_type(UserType::Staff)
             .vip(false)
             .reply_parent_msg_id("")
             .extra_tags(extra_tags)
@@ -408,6 +395,75 @@ mod test {
         assert_eq!(Ok(expected_message), twitch_message);
     }
 
+    #[test]
+    fn should_parse_shared_chat_message() {
+        let msg: String = "@badges=;color=#1E90FF;display-name=dallas;emotes=;id=9b1c4e2d-2a8f-4c7e-b0f5-0d3f6d1e2c44;mod=0;room-id=12345678;source-badges=moderator/1;source-id=5f0a3e1c-7b2d-4e6f-9a8b-1c2d3e4f5a6b;source-only=0;source-room-id=87654321;subscriber=0;tmi-sent-ts=1726159217331;turbo=0;user-id=11223344;user-type= :dallas!dallas@dallas.tmi.twitch.tv PRIVMSG #ronni :hello from the other side".into();
+        let parser: TrirkParser = TrirkParser::new();
+        let twitch_message = parser.parse(msg).unwrap();
+        let tags = twitch_message.tags().as_ref().unwrap();
+        let mut source_badges = Badge::default();
+        source_badges.set_moderator("1".into());
+        assert_eq!("87654321", tags.source_room_id());
+        assert_eq!("5f0a3e1c-7b2d-4e6f-9a8b-1c2d3e4f5a6b", tags.source_id());
+        assert_eq!(&source_badges, tags.source_badges());
+        assert!(!tags.source_only());
+        assert!(tags.is_from_shared_chat());
+        assert!(tags.extra_tags().is_empty());
+    }
+
+    #[test]
+    fn should_not_flag_own_channel_message_as_shared() {
+        let msg: String = "@room-id=12345678;source-room-id=12345678;source-id=abc :dallas!dallas@dallas.tmi.twitch.tv PRIVMSG #dallas :hi".into();
+        let parser: TrirkParser = TrirkParser::new();
+        let twitch_message = parser.parse(msg).unwrap();
+        let tags = twitch_message.tags().as_ref().unwrap();
+        assert!(!tags.is_from_shared_chat());
+    }
+
+    #[test]
+    fn should_compute_lag_from_receive_time() {
+        let msg: String =
+            "@room-id=12345678;tmi-sent-ts=1642715756806 :tmi.twitch.tv CLEARCHAT #dallas".into();
+        let parser: TrirkParser = TrirkParser::new();
+        let twitch_message = parser.parse(msg).unwrap();
+        assert_eq!(None, twitch_message.lag());
+        let twitch_message =
+            twitch_message.with_received_at(Timestamp::from_millis(1642715757056).unwrap());
+        assert_eq!(
+            Some(std::time::Duration::from_millis(250)),
+            twitch_message.lag()
+        );
+        assert_eq!(
+            Some(1642715756806),
+            twitch_message
+                .tags()
+                .as_ref()
+                .and_then(|tags| tags.tmi_sent_ts().map(|ts| ts.as_millis()))
+        );
+    }
+
+    #[test]
+    fn should_parse_usernotice_msg_id_and_emote_ranges() {
+        let msg: String = "@badges=;emotes=25:0-4,12-16/1902:6-10;login=ronni;msg-id=resub;room-id=12345678 :tmi.twitch.tv USERNOTICE #dallas :Kappa Keepo Kappa\r\n".into();
+        let parser: TrirkParser = TrirkParser::new();
+        let twitch_message = parser.parse(msg).unwrap();
+        let tags = twitch_message.tags().as_ref().unwrap();
+        assert_eq!("resub", tags.message_id());
+        assert_eq!("ronni", tags.login());
+        assert_eq!(
+            &vec![
+                Emote::new("25", 0, 4),
+                Emote::new("25", 12, 16),
+                Emote::new("1902", 6, 10)
+            ],
+            tags.emotes()
+        );
+        assert_eq!(
+            &Some("Kappa Keepo Kappa".to_string()),
+            twitch_message.parameters()
+        );
+    }
+
     #[test]
     fn should_parse_message_without_tags() {
         let msg: String =
@@ -415,7 +471,7 @@ mod test {
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
         let source = Source::new("lovingt3s", "lovingt3s.tmi.twitch.tv");
-        let command = Command::new(CommandType::PrivMSG, "lovingt3s");
+        let command = Command::new(CommandType::PrivMSG, channel("lovingt3s"));
         let parameters = "!dilly";
         let expected_message = TwitchMessage::new(Some(parameters), command, Some(source), None);
         assert_eq!(Ok(expected_message), twitch_message);
@@ -432,12 +488,33 @@ mod test {
         )
     }
 
+    #[test]
+    fn should_parse_ping_with_server() {
+        let parser: TrirkParser = TrirkParser::new();
+        let twitch_message = parser.parse("PING :tmi.twitch.tv\r\n").unwrap();
+        assert_eq!(&CommandType::Ping, twitch_message.command().command());
+        assert_eq!(
+            &Some("tmi.twitch.tv".to_string()),
+            twitch_message.parameters()
+        );
+    }
+
+    #[test]
+    fn should_parse_pong() {
+        let parser: TrirkParser = TrirkParser::new();
+        let twitch_message = parser
+            .parse(":tmi.twitch.tv PONG tmi.twitch.tv :trirk\r\n")
+            .unwrap();
+        assert_eq!(&CommandType::Pong, twitch_message.command().command());
+        assert_eq!(&Some("trirk".to_string()), twitch_message.parameters());
+    }
+
     #[test]
     fn should_parse_notice() {
         let msg: String = "@msg-id=delete_message_success :tmi.twitch.tv NOTICE #bar :The message from foo is now deleted.".into();
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
-        let command = Command::new(CommandType::Notice, "");
+        let command = Command::new(CommandType::Notice, channel("bar"));
         let source = Source::new("", "tmi.twitch.tv");
         let tags = Tags::builder()
             .message_id("delete_message_success")
@@ -457,7 +534,7 @@ mod test {
         let msg: String = "@msg-id=whisper_restricted;target-user-id=12345678 :tmi.twitch.tv NOTICE #bar :Your settings prevent you from sending this whisper.".into();
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
-        let command = Command::new(CommandType::Notice, "");
+        let command = Command::new(CommandType::Notice, channel("bar"));
         let source = Source::new("", "tmi.twitch.tv");
         let tags = Tags::builder()
             .message_id("whisper_restricted")
@@ -478,11 +555,11 @@ mod test {
         let msg: String = "@room-id=12345678;target-user-id=87654321;tmi-sent-ts=1642715756806 :tmi.twitch.tv CLEARCHAT #dallas :ronni".into();
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
-        let command = Command::new(CommandType::ClearChat, "");
+        let command = Command::new(CommandType::ClearChat, channel("dallas"));
         let source = Source::new("", "tmi.twitch.tv");
         let tags = Tags::builder()
             .room_id("12345678")
-            .tmi_sent_ts(1642715756806usize)
+            .tmi_sent_ts(Timestamp::from_millis(1642715756806))
             .target_user_id("87654321")
             .build()
             .unwrap();
@@ -495,11 +572,11 @@ mod test {
         let msg: String = "@ban-duration=350;room-id=12345678;target-user-id=87654321;tmi-sent-ts=1642719320727 :tmi.twitch.tv CLEARCHAT #dallas :ronni".into();
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
-        let command = Command::new(CommandType::ClearChat, "");
+        let command = Command::new(CommandType::ClearChat, channel("dallas"));
         let source = Source::new("", "tmi.twitch.tv");
         let tags = Tags::builder()
             .room_id("12345678")
-            .tmi_sent_ts(1642719320727usize)
+            .tmi_sent_ts(Timestamp::from_millis(1642719320727))
             .target_user_id("87654321")
             .ban_duration(350usize)
             .build()
@@ -513,11 +590,11 @@ mod test {
         let msg: String = "@login=ronni;room-id=;target-msg-id=abc-123-def;tmi-sent-ts=1642720582342 :tmi.twitch.tv CLEARMSG #dallas :HeyGuys".into();
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
-        let command = Command::new(CommandType::ClearMessage, "");
+        let command = Command::new(CommandType::ClearMessage, channel("dallas"));
         let source = Source::new("", "tmi.twitch.tv");
         let tags = Tags::builder()
             .room_id("")
-            .tmi_sent_ts(1642720582342usize)
+            .tmi_sent_ts(Timestamp::from_millis(1642720582342))
             .target_message_id("abc-123-def")
             .login("ronni")
             .build()
@@ -527,27 +604,26 @@ mod test {
         assert_eq!(Ok(expected_message), twitch_message);
     }
 
-
     #[test]
     fn should_parse_globaluserstate() {
         let msg: String = "@badge-info=subscriber/8;badges=subscriber/6;color=#0D4200;display-name=dallas;emote-sets=0,33,50,237,793,2126,3517,4578,5569,9400,10337,12239;turbo=0;user-id=12345678;user-type=admin :tmi.twitch.tv GLOBALUSERSTATE".into();
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
         let source = Source::new("", "tmi.twitch.tv");
-        let command = Command::new(CommandType::GlobalUserState, "");
+        let command = Command::new(CommandType::GlobalUserState, None);
         let mut badges = Badge::default();
         let mut extra_tags = HashMap::new();
-        extra_tags.insert("@badge-info".into(), "subscriber/8".into());
+        extra_tags.insert("badge-info".into(), "subscriber/8".into());
         badges.set_subscriber("6".into());
         let tags = Tags::builder()
             .badges(badges)
-            .color("#0D4200")
+            .color(Color::new(0x0D, 0x42, 0x00))
             .display_name("dallas")
             .user_id("12345678")
             .emote_sets(vec![
                 0, 33, 50, 237, 793, 2126, 3517, 4578, 5569, 9400, 10337, 12239,
             ])
-            .user_type("admin")
+            .user_type(UserType::Admin)
             .user_id("12345678")
             .extra_tags(extra_tags)
             .build()
@@ -561,32 +637,193 @@ mod test {
         let msg: String = "@emote-only=0;followers-only=0;r9k=0;slow=0;subs-only=0 :tmi.twitch.tv ROOMSTATE #dallas".into();
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
-        let command = Command::new(CommandType::RoomState, "");
+        let command = Command::new(CommandType::RoomState, channel("dallas"));
         let source = Source::new("", "tmi.twitch.tv");
         let tags = Tags::builder()
-            .emote_only(false)
-            .followers_only(false)
-            .r9k(false)
+            .emote_only(Some(false))
+            .followers_only(Some(0))
+            .r9k(Some(false))
             .slow(0usize)
-            .subs_only(false)
+            .subs_only(Some(false))
             .build()
             .unwrap();
         let expected_message = TwitchMessage::new::<&str>(None, command, Some(source), Some(tags));
         assert_eq!(Ok(expected_message), twitch_message);
     }
 
+    #[test]
+    fn should_parse_cap_subcommand() {
+        let parser: TrirkParser = TrirkParser::new();
+        let twitch_message = parser.parse(":tmi.twitch.tv CAP * NAK :twitch.tv/bogus");
+        let command = Command::new(CommandType::Cap("NAK".into()), None);
+        let source = Source::new("", "tmi.twitch.tv");
+        let expected_message =
+            TwitchMessage::new(Some("twitch.tv/bogus"), command, Some(source), None);
+        assert_eq!(Ok(expected_message), twitch_message);
+    }
+
     #[test]
     fn should_parse_roomstate_slow() {
         let msg: String = "@slow=10 :tmi.twitch.tv ROOMSTATE #dallas".into();
         let parser: TrirkParser = TrirkParser::new();
         let twitch_message = parser.parse(msg);
-        let command = Command::new(CommandType::RoomState, "");
+        let command = Command::new(CommandType::RoomState, channel("dallas"));
         let source = Source::new("", "tmi.twitch.tv");
         let tags = Tags::builder().slow(10usize).build().unwrap();
         let expected_message = TwitchMessage::new::<&str>(None, command, Some(source), Some(tags));
         assert_eq!(Ok(expected_message), twitch_message);
     }
 
+    #[test]
+    fn should_normalize_channel_name() {
+        let channel = ChannelName::new("#EvaZord").unwrap();
+        assert_eq!("evazord", channel.as_str());
+        assert_eq!("#evazord", channel.to_string());
+        assert!(ChannelName::new("#").is_err());
+        assert!(ChannelName::new("eva zord").is_err());
+        assert!(ChannelName::new("evazord\r\nPRIVMSG").is_err());
+    }
+
+    #[test]
+    fn should_parse_and_format_color() {
+        let color: Color = "#1e90ff".parse().unwrap();
+        assert_eq!(Color::new(0x1E, 0x90, 0xFF), color);
+        assert_eq!("#1E90FF", color.to_string());
+        assert!("1E90FF".parse::<Color>().is_err());
+        assert!("#1E90F".parse::<Color>().is_err());
+        assert!("#1E90FG".parse::<Color>().is_err());
+        assert!("#+F+F+F".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn should_parse_user_type() {
+        assert_eq!(UserType::Normal, UserType::from(""));
+        assert_eq!(UserType::GlobalMod, UserType::from("global_mod"));
+        assert_eq!("global_mod", UserType::GlobalMod.to_string());
+        assert_eq!(UserType::Unknown("vip".into()), UserType::from("vip"));
+    }
+
+    #[test]
+    fn should_not_panic_on_hostile_input() {
+        let parser: TrirkParser = TrirkParser::new();
+        for msg in [
+            "@tmi-sent-ts=18446744073709551615;ban-duration=99999999999999999999999 :tmi.twitch.tv CLEARCHAT #dallas",
+            "@color=#ÿÿÿ;emotes=25:4-0,ç:é-ü :tmi.twitch.tv PRIVMSG #dallas :é",
+            "ção!ção@ção JOIN #ção",
+            "@ :",
+            ": PART",
+        ] {
+            let _ = parser.parse(msg);
+        }
+    }
+
+    #[test]
+    fn should_tell_why_a_line_is_unparsable() {
+        let parser: TrirkParser = TrirkParser::new();
+        let kind = |msg| parser.parse(msg).unwrap_err().kind();
+        assert_eq!(UnparsableErrorKind::Empty, kind("\r\n"));
+        assert_eq!(UnparsableErrorKind::MissingCommand, kind("@badges="));
+        assert_eq!(UnparsableErrorKind::MissingCommand, kind(":tmi.twitch.tv"));
+        assert_eq!(UnparsableErrorKind::UnknownCommand, kind("xablau #dallas"));
+        let invalid_color = "#1E90FG".parse::<Color>().unwrap_err();
+        assert_eq!(UnparsableErrorKind::InvalidColor, invalid_color.kind());
+        let invalid_channel = ChannelName::new("eva zord").unwrap_err();
+        assert_eq!(UnparsableErrorKind::InvalidChannel, invalid_channel.kind());
+    }
+
+    #[test]
+    fn should_drop_timestamps_out_of_range() {
+        // more milliseconds than a u64 holds
+        let msg = "@tmi-sent-ts=99999999999999999999 :tmi.twitch.tv CLEARCHAT #dallas";
+        let twitch_message = TrirkParser::new().parse(msg).unwrap();
+        assert_eq!(&None, twitch_message.tags().as_ref().unwrap().tmi_sent_ts());
+        // SystemTime's range depends on the platform, out of it is None
+        // rather than a panic
+        for millis in [0, 1_700_000_000_000, u64::MAX] {
+            let representable = UNIX_EPOCH
+                .checked_add(Duration::from_millis(millis))
+                .is_some();
+            assert_eq!(representable, Timestamp::from_millis(millis).is_some());
+        }
+    }
+
+    #[test]
+    fn should_unescape_tag_values() {
+        let msg = "@display-name=ron\\sni;system-msg=a\\:b\\\\c\\r\\nd\\;flags=x\\ :tmi.twitch.tv USERNOTICE #dallas";
+        let twitch_message = TrirkParser::new().parse(msg).unwrap();
+        let tags = twitch_message.tags().as_ref().unwrap();
+        assert_eq!("ron ni", tags.display_name().as_str());
+        assert_eq!("a;b\\c\r\nd", tags.extra_tags()["system-msg"]);
+        assert_eq!("x", tags.extra_tags()["flags"]);
+    }
+
+    #[test]
+    fn should_parse_bytes_with_invalid_utf8() {
+        let parser: TrirkParser = TrirkParser::new();
+        let twitch_message = parser
+            .parse_bytes(b":foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :ol\xE1 mundo\r\n")
+            .unwrap();
+        assert_eq!(&CommandType::PrivMSG, twitch_message.command().command());
+        assert_eq!(
+            &Some("ol\u{FFFD} mundo".to_string()),
+            twitch_message.parameters()
+        );
+    }
+
+    #[test]
+    fn should_decode_registered_tags() {
+        #[derive(Clone, PartialEq, Debug)]
+        struct CumulativeMonths(u32);
+        #[derive(Clone, PartialEq, Debug)]
+        struct FirstMessage(bool);
+
+        let parser = TrirkParser::new()
+            .with_tag_decoder("msg-param-cumulative-months", |value| {
+                value.parse().ok().map(CumulativeMonths)
+            })
+            .with_tag_decoder("first-msg", |value| Some(FirstMessage(value == "1")));
+        let msg = "@first-msg=1;msg-id=resub;msg-param-cumulative-months=6 :tmi.twitch.tv USERNOTICE #dallas";
+        let twitch_message = parser.parse(msg).unwrap();
+        let tags = twitch_message.tags().as_ref().unwrap();
+        assert_eq!(
+            Some(&CumulativeMonths(6)),
+            tags.get::<CumulativeMonths>("msg-param-cumulative-months")
+        );
+        assert_eq!(
+            Some(&FirstMessage(true)),
+            tags.get::<FirstMessage>("first-msg")
+        );
+        assert_eq!(
+            None,
+            tags.get::<FirstMessage>("msg-param-cumulative-months")
+        );
+        assert_eq!(None, tags.get::<String>("first-msg"));
+        assert_eq!(
+            Some(&"6".to_string()),
+            tags.extra_tags().get("msg-param-cumulative-months")
+        );
+        assert_eq!(twitch_message, parser.parse(msg).unwrap());
+    }
+
+    #[test]
+    fn should_keep_decoded_tags_of_the_same_type_apart() {
+        let months = |value: &str| value.parse::<u32>().ok();
+        let parser = TrirkParser::new()
+            .with_tag_decoder("msg-param-cumulative-months", months)
+            .with_tag_decoder("msg-param-streak-months", months);
+        let msg = "@msg-param-cumulative-months=6;msg-param-streak-months=2 :tmi.twitch.tv USERNOTICE #dallas";
+        let twitch_message = parser.parse(msg).unwrap();
+        let tags = twitch_message.tags().as_ref().unwrap();
+        assert_eq!(Some(&6), tags.get::<u32>("msg-param-cumulative-months"));
+        assert_eq!(Some(&2), tags.get::<u32>("msg-param-streak-months"));
+        assert_ne!(
+            twitch_message,
+            parser
+                .parse("@msg-param-cumulative-months=2;msg-param-streak-months=6 :tmi.twitch.tv USERNOTICE #dallas")
+                .unwrap()
+        );
+    }
+
     #[test]
     #[should_panic]
     fn should_panic_with_empty_message() {
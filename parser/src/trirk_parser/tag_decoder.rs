@@ -0,0 +1,83 @@
+use std::{any::Any, fmt::Debug, sync::Arc};
+
+/// A typed value produced by a tag decoder registered on
+/// [`TrirkParser`](super::TrirkParser).
+pub trait TagValue: Any + Debug + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn clone_box(&self) -> Box<dyn TagValue>;
+    fn eq_dyn(&self, other: &dyn TagValue) -> bool;
+}
+
+impl<T: Any + Clone + PartialEq + Debug + Send + Sync> TagValue for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn clone_box(&self) -> Box<dyn TagValue> {
+        Box::new(self.clone())
+    }
+
+    fn eq_dyn(&self, other: &dyn TagValue) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<T>()
+            .is_some_and(|other| self == other)
+    }
+}
+
+pub(crate) type TagDecoder = Arc<dyn Fn(&str) -> Option<Box<dyn TagValue>> + Send + Sync>;
+
+/// Values decoded by custom tag decoders, indexed by tag name and type so
+/// decoders producing the same type for different tags do not collide.
+#[derive(Default, Debug)]
+pub struct DecodedTags(Vec<(String, Box<dyn TagValue>)>);
+
+impl DecodedTags {
+    pub(crate) fn insert(&mut self, name: &str, value: Box<dyn TagValue>) {
+        let type_id = value.as_any().type_id();
+        match self
+            .0
+            .iter_mut()
+            .find(|(key, old)| key == name && old.as_any().type_id() == type_id)
+        {
+            Some((_, old)) => *old = value,
+            None => self.0.push((name.into(), value)),
+        }
+    }
+
+    pub fn get<T: Any>(&self, name: &str) -> Option<&T> {
+        self.0
+            .iter()
+            .filter(|(key, _)| key == name)
+            .find_map(|(_, value)| value.as_any().downcast_ref::<T>())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Clone for DecodedTags {
+    fn clone(&self) -> Self {
+        Self(
+            self.0
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone_box()))
+                .collect(),
+        )
+    }
+}
+
+impl PartialEq for DecodedTags {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self.0.iter().all(|(name, value)| {
+                other
+                    .0
+                    .iter()
+                    .any(|(key, other)| key == name && value.eq_dyn(other.as_ref()))
+            })
+    }
+}
+
+impl Eq for DecodedTags {}
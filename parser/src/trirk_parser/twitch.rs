@@ -1,14 +1,27 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    ops::{Deref, Range},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use derive_builder::Builder;
 use derive_getters::Getters;
 
-#[derive(PartialEq, Eq, Debug, Getters)]
+use super::{
+    error::{UnparsableError, UnparsableErrorKind},
+    tag_decoder::DecodedTags,
+};
+
+#[derive(PartialEq, Eq, Debug, Clone, Getters)]
 pub struct TwitchMessage {
-    parameters: Option<String>,
-    command: Command,
-    source: Option<Source>,
-    tags: Option<Tags>,
+    pub(crate) parameters: Option<String>,
+    pub(crate) command: Command,
+    pub(crate) source: Option<Source>,
+    pub(crate) tags: Option<Tags>,
+    pub(crate) received_at: Option<Timestamp>,
 }
 
 impl TwitchMessage {
@@ -24,39 +37,117 @@ impl TwitchMessage {
             command,
             source,
             tags,
+            received_at: None,
         }
     }
+
+    /// Stamps the message with the moment it was read from the socket.
+    pub fn with_received_at(mut self, received_at: Timestamp) -> Self {
+        self.received_at = Some(received_at);
+        self
+    }
+
+    /// Time between Twitch sending the message (`tmi-sent-ts`) and the
+    /// client receiving it. `None` when either side is unknown or the
+    /// clocks disagree so much that the message seems to arrive before it was sent.
+    pub fn lag(&self) -> Option<Duration> {
+        let sent_at = (*self.tags.as_ref()?.tmi_sent_ts())?;
+        self.received_at?.duration_since(&sent_at)
+    }
 }
 
-#[derive(Builder, Clone, PartialEq, Eq, Debug, Getters)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Timestamp(SystemTime);
+
+impl Timestamp {
+    #[inline(always)]
+    pub fn now() -> Self {
+        Self(SystemTime::now())
+    }
+
+    /// `None` when the time does not fit the platform's [`SystemTime`].
+    #[inline(always)]
+    pub fn from_millis(millis: u64) -> Option<Self> {
+        UNIX_EPOCH
+            .checked_add(Duration::from_millis(millis))
+            .map(Self)
+    }
+
+    pub fn as_millis(&self) -> u64 {
+        self.0
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as u64)
+    }
+
+    pub fn system_time(&self) -> SystemTime {
+        self.0
+    }
+
+    pub fn duration_since(&self, earlier: &Timestamp) -> Option<Duration> {
+        self.0.duration_since(earlier.0).ok()
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(value: SystemTime) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(value: Timestamp) -> Self {
+        value.0
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<Timestamp> for chrono::DateTime<chrono::Utc> {
+    fn from(value: Timestamp) -> Self {
+        value.0.into()
+    }
+}
+
+#[derive(Builder, Default, Clone, PartialEq, Eq, Debug, Getters)]
 #[builder(setter(into))]
 pub struct Tags {
-    badges: Badge,
-    color: String,
-    display_name: String,
-    emote_only: bool,
-    emotes: Vec<Emote>,
-    id: String,
-    r#mod: bool,
-    room_id: String,
-    subscriber: bool,
-    turbo: bool,
-    tmi_sent_ts: usize,
-    user_id: String,
-    user_type: String,
-    vip: bool,
-    reply_parent_msg_id: String,
-    target_user_id: String,
-    message_id: String,
-    ban_duration: usize,
-    login: String,
-    target_message_id: String,
-    emote_sets: Vec<usize>,
-    followers_only: bool,
-    r9k: bool,
-    slow: usize,
-    subs_only: bool,
-    extra_tags: HashMap<String, String>,
+    pub(crate) badges: Badge,
+    pub(crate) color: Option<Color>,
+    pub(crate) display_name: TagStr,
+    /// `None` when the message did not carry the tag.
+    pub(crate) emote_only: Option<bool>,
+    pub(crate) emotes: Vec<Emote>,
+    pub(crate) id: TagStr,
+    pub(crate) r#mod: bool,
+    pub(crate) room_id: TagStr,
+    pub(crate) subscriber: bool,
+    pub(crate) turbo: bool,
+    pub(crate) tmi_sent_ts: Option<Timestamp>,
+    pub(crate) user_id: TagStr,
+    pub(crate) user_type: UserType,
+    pub(crate) vip: bool,
+    pub(crate) reply_parent_msg_id: TagStr,
+    pub(crate) target_user_id: TagStr,
+    pub(crate) message_id: TagStr,
+    pub(crate) ban_duration: usize,
+    pub(crate) login: TagStr,
+    pub(crate) target_message_id: TagStr,
+    pub(crate) emote_sets: Vec<usize>,
+    /// Minimum follow age in minutes, `-1` when followers-only mode is off.
+    /// Like the other ROOMSTATE settings, `None` when the message did not
+    /// carry the tag.
+    pub(crate) followers_only: Option<isize>,
+    pub(crate) r9k: Option<bool>,
+    /// Slow mode in seconds, `None` when the message did not carry the tag
+    /// (ROOMSTATE updates only send the settings that changed).
+    pub(crate) slow: Option<usize>,
+    pub(crate) subs_only: Option<bool>,
+    pub(crate) source_room_id: TagStr,
+    pub(crate) source_id: TagStr,
+    pub(crate) source_badges: Badge,
+    pub(crate) source_only: bool,
+    pub(crate) extra_tags: HashMap<String, String>,
+    #[getter(skip)]
+    pub(crate) decoded: DecodedTags,
 }
 
 impl Tags {
@@ -65,17 +156,17 @@ impl Tags {
         builder
             .vip(false)
             .badges(Badge::default())
-            .color("")
+            .color(None)
             .display_name("")
-            .emote_only(false)
+            .emote_only(None)
             .emotes(vec![])
             .r#mod(false)
             .room_id("")
             .subscriber(false)
             .turbo(false)
-            .tmi_sent_ts(usize::MIN)
+            .tmi_sent_ts(None)
             .user_id("")
-            .user_type("")
+            .user_type(UserType::Normal)
             .target_user_id("")
             .message_id("")
             .reply_parent_msg_id("")
@@ -83,14 +174,108 @@ impl Tags {
             .ban_duration(0usize)
             .target_message_id("")
             .emote_sets(vec![])
-            .followers_only(false)
-            .r9k(false)
-            .slow(usize::MIN)
-            .subs_only(false)
+            .followers_only(None)
+            .r9k(None)
+            .slow(None)
+            .subs_only(None)
+            .source_room_id("")
+            .source_id("")
+            .source_badges(Badge::default())
+            .source_only(false)
             .extra_tags(HashMap::new())
+            .decoded(DecodedTags::default())
             .id("");
         builder
     }
+
+    /// Returns the value a decoder registered for the tag `name` produced as
+    /// a `T`, if the tag was present and decoded successfully.
+    pub fn get<T: std::any::Any>(&self, name: &str) -> Option<&T> {
+        self.decoded.get::<T>(name)
+    }
+
+    /// Returns `true` when the message was mirrored from another channel
+    /// taking part in a shared chat session.
+    pub fn is_from_shared_chat(&self) -> bool {
+        !self.source_room_id.is_empty() && self.source_room_id != self.room_id
+    }
+}
+
+/// Text value of a known tag. The values of a message share one copy of its
+/// tag section instead of each owning a `String`.
+#[derive(Clone, Default)]
+pub struct TagStr {
+    section: Option<Arc<str>>,
+    range: Range<usize>,
+}
+
+impl TagStr {
+    /// `section[range]`, `range` must lie on character boundaries.
+    #[inline(always)]
+    pub(crate) fn shared(section: &Arc<str>, range: Range<usize>) -> Self {
+        Self {
+            section: Some(section.clone()),
+            range,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.section
+            .as_deref()
+            .map_or("", |section| &section[self.range.clone()])
+    }
+}
+
+impl Deref for TagStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for TagStr {
+    fn from(value: &str) -> Self {
+        Self::shared(&Arc::from(value), 0..value.len())
+    }
+}
+
+impl From<String> for TagStr {
+    fn from(value: String) -> Self {
+        value.as_str().into()
+    }
+}
+
+impl PartialEq for TagStr {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for TagStr {}
+
+impl PartialEq<str> for TagStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<TagStr> for str {
+    fn eq(&self, other: &TagStr) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl Debug for TagStr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for TagStr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Getters)]
@@ -114,16 +299,164 @@ impl Emote {
 #[derive(PartialEq, Eq, Debug, Clone, Getters)]
 pub struct Command {
     command: CommandType,
-    channel: String,
+    channel: Option<ChannelName>,
 }
 
 impl Command {
     #[inline(always)]
-    pub fn new<T: Into<String>>(command: CommandType, channel: T) -> Self {
-        Self {
-            command,
-            channel: channel.into(),
+    pub fn new(command: CommandType, channel: Option<ChannelName>) -> Self {
+        Self { command, channel }
+    }
+}
+
+/// A Twitch channel login, stored lowercase and without the leading `#`.
+///
+/// `Display` renders the IRC form (`#channel`), use [`ChannelName::as_str`]
+/// for the bare login.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone)]
+pub struct ChannelName(String);
+
+impl ChannelName {
+    const MAX_LEN: usize = 25;
+
+    pub fn new<T: AsRef<str>>(channel: T) -> Result<Self, UnparsableError> {
+        let channel = channel.as_ref().trim();
+        let channel = channel.strip_prefix('#').unwrap_or(channel);
+        if channel.is_empty() || channel.len() > Self::MAX_LEN {
+            return Err(UnparsableError::new(
+                UnparsableErrorKind::InvalidChannel,
+                format!(
+                    "invalid channel name '{channel}', expected 1 to {} characters",
+                    Self::MAX_LEN
+                ),
+            ));
+        }
+        if !channel
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(UnparsableError::new(
+                UnparsableErrorKind::InvalidChannel,
+                format!(
+                    "invalid channel name '{channel}', only letters, digits and '_' are allowed"
+                ),
+            ));
+        }
+        Ok(Self(channel.to_ascii_lowercase()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for ChannelName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+impl FromStr for ChannelName {
+    type Err = UnparsableError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<&str> for ChannelName {
+    type Error = UnparsableError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl TryFrom<String> for ChannelName {
+    type Error = UnparsableError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+#[derive(Default, PartialEq, Eq, Debug, Clone)]
+pub enum UserType {
+    /// Regular chatter, sent by Twitch as an empty `user-type`.
+    #[default]
+    Normal,
+    Mod,
+    GlobalMod,
+    Admin,
+    Staff,
+    Unknown(String),
+}
+
+impl From<&str> for UserType {
+    fn from(value: &str) -> Self {
+        match value {
+            "" => Self::Normal,
+            "mod" => Self::Mod,
+            "global_mod" => Self::GlobalMod,
+            "admin" => Self::Admin,
+            "staff" => Self::Staff,
+            unk => Self::Unknown(unk.into()),
+        }
+    }
+}
+
+impl Display for UserType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Self::Normal => "",
+            Self::Mod => "mod",
+            Self::GlobalMod => "global_mod",
+            Self::Admin => "admin",
+            Self::Staff => "staff",
+            Self::Unknown(value) => value,
+        };
+        write!(f, "{value}")
+    }
+}
+
+/// RGB chat color, parsed from and formatted as `#RRGGBB`.
+#[derive(Default, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    #[inline(always)]
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+}
+
+impl FromStr for Color {
+    type Err = UnparsableError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            UnparsableError::new(
+                UnparsableErrorKind::InvalidColor,
+                format!("invalid color '{s}', expected #RRGGBB"),
+            )
+        };
+        let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+        // from_str_radix alone would accept a sign, e.g. "+F"
+        if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(invalid());
         }
+        let channel = |range| u8::from_str_radix(&hex[range], 16).map_err(|_| invalid());
+        Ok(Self::new(channel(0..2)?, channel(2..4)?, channel(4..6)?))
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.red, self.green, self.blue)
     }
 }
 
@@ -137,13 +470,16 @@ pub enum CommandType {
     HostTarget,
     ClearMessage,
     Ping,
-    Cap,
+    Pong,
+    /// The subcommand, e.g. `ACK` or `NAK`.
+    Cap(String),
     GlobalUserState,
     UserState,
     RoomState,
     Reconnect,
     Numeric(u16),
     UserNotice,
+    Whisper,
     Unknown(String),
 }
 
@@ -156,7 +492,8 @@ impl From<&str> for CommandType {
             "CLEARCHAT" => Self::ClearChat,
             "HOSTTARGEtT" => Self::HostTarget,
             "PING" => Self::Ping,
-            "CAP" => Self::Cap,
+            "PONG" => Self::Pong,
+            "CAP" => Self::Cap(String::new()),
             "GLOBALUSERSTATE" => Self::GlobalUserState,
             "USERSTATE" => Self::UserState,
             "ROOMSTATE" => Self::RoomState,
@@ -164,6 +501,7 @@ impl From<&str> for CommandType {
             "CLEARMSG" => Self::ClearMessage,
             "JOIN" => Self::Join,
             "USERNOTICE" => Self::UserNotice,
+            "WHISPER" => Self::Whisper,
             v if value.parse::<u16>().is_ok() => Self::Numeric(v.parse().unwrap()),
             cmd => Self::Unknown(cmd.into()),
         }
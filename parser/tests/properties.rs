@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use parser::{trirk_parser::TrirkParser, ChannelName, CommandType};
+use proptest::prelude::*;
+
+/// Keys the parser has no field for, so their values land in `extra_tags`.
+fn extra_tag_key() -> impl Strategy<Value = String> {
+    "x-[a-z0-9-]{0,15}"
+}
+
+fn tag_key() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just("badges".to_string()),
+        Just("color".to_string()),
+        Just("emotes".to_string()),
+        Just("tmi-sent-ts".to_string()),
+        Just("user-type".to_string()),
+        Just("ban-duration".to_string()),
+        Just("emote-sets".to_string()),
+        Just("source-badges".to_string()),
+        "[a-z][a-z0-9-]{0,15}",
+    ]
+}
+
+fn tag_value() -> impl Strategy<Value = String> {
+    // any text, including the characters that have to be escaped
+    "[^\0]{0,24}"
+}
+
+fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn nick() -> impl Strategy<Value = String> {
+    "[a-z0-9_]{1,25}"
+}
+
+fn command() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just("PRIVMSG".to_string()),
+        Just("USERNOTICE".to_string()),
+        Just("ROOMSTATE".to_string()),
+        Just("CLEARCHAT".to_string()),
+        Just("NOTICE".to_string()),
+        Just("PART".to_string()),
+        Just("JOIN".to_string()),
+        "[0-9]{3}",
+    ]
+}
+
+fn trailing() -> impl Strategy<Value = Option<String>> {
+    proptest::option::of("[^\r\n\0]{0,64}")
+}
+
+proptest! {
+    #[test]
+    fn parse_never_panics_on_arbitrary_text(line in any::<String>()) {
+        let _ = TrirkParser::new().parse(line);
+    }
+
+    #[test]
+    fn parse_never_panics_on_irc_shaped_text(
+        prefix in prop_oneof![Just("@"), Just(":"), Just("")],
+        body in "[@:;=!/#, a-zA-Z0-9\u{80}-\u{10FFFF}-]{0,128}",
+    ) {
+        let _ = TrirkParser::new().parse(format!("{prefix}{body}"));
+    }
+
+    #[test]
+    fn parse_round_trips_generated_lines(
+        tags in proptest::collection::vec(
+            (prop_oneof![tag_key(), extra_tag_key()], tag_value()),
+            0..8,
+        ),
+        nick in nick(),
+        command in command(),
+        channel in nick(),
+        trailing in trailing(),
+    ) {
+        let mut line = String::new();
+        if !tags.is_empty() {
+            let tags: Vec<String> = tags
+                .iter()
+                .map(|(key, value)| format!("{key}={}", escape_tag_value(value)))
+                .collect();
+            line.push_str(&format!("@{} ", tags.join(";")));
+        }
+        line.push_str(&format!(":{nick}!{nick}@{nick}.tmi.twitch.tv {command} #{channel}"));
+        if let Some(trailing) = &trailing {
+            line.push_str(&format!(" :{trailing}"));
+        }
+        line.push_str("\r\n");
+
+        let message = TrirkParser::new().parse(&line).unwrap();
+
+        let source = message.source().as_ref().unwrap();
+        prop_assert_eq!(&nick, &source.nick());
+        prop_assert_eq!(format!("{nick}.tmi.twitch.tv"), source.host());
+        let expected_command = match CommandType::from(command.as_str()) {
+            CommandType::Part(_) => CommandType::Part(vec![nick.clone()]),
+            command => command,
+        };
+        prop_assert_eq!(&expected_command, message.command().command());
+        prop_assert_eq!(&ChannelName::new(&channel).ok(), message.command().channel());
+        prop_assert_eq!(&trailing, message.parameters());
+        prop_assert_eq!(!tags.is_empty(), message.tags().is_some());
+        // the last value of a repeated key wins
+        let expected_extra: HashMap<String, String> = tags
+            .iter()
+            .filter(|(key, _)| key.starts_with("x-"))
+            .cloned()
+            .collect();
+        let extra = message.tags().as_ref().map(|tags| tags.extra_tags().clone()).unwrap_or_default();
+        let extra: HashMap<String, String> =
+            extra.into_iter().filter(|(key, _)| key.starts_with("x-")).collect();
+        prop_assert_eq!(expected_extra, extra);
+    }
+}